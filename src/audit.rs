@@ -0,0 +1,47 @@
+use serde::Serialize;
+
+use crate::notify::MatchContext;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// One line of the `--audit-file`, written after every successful restart.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    pub timestamp: i64,
+    pub watch: &'a str,
+    pub pattern: &'a str,
+    pub line: &'a str,
+    pub restarted: &'a [String],
+    #[serde(flatten)]
+    pub context: &'a MatchContext,
+}
+
+/// Append-only JSON lines file of restarts.
+#[derive(Debug)]
+pub struct AuditLog {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("Failed to open audit file '{}': {err}", path.display()))?;
+
+        Ok(AuditLog {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Appends `entry` and flushes it straight away so it survives a crash.
+    pub fn record(&self, entry: &AuditEntry) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, entry)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
@@ -0,0 +1,1193 @@
+use crate::audit::AuditLog;
+use crate::config::{
+    expand_env_vars, load_config_file, load_pattern_file, validate_configs, CommandMode,
+    ContainerRestartConfig, Stream,
+};
+use crate::context::{self, TlsFiles};
+use crate::duration;
+use crate::health::{Health, Reply};
+use crate::history::History;
+use crate::identity;
+use crate::limits::{RateLimiter, Throttle};
+use crate::logging::{self, LogFormat};
+use crate::matcher::MatchOptions;
+use crate::metrics::Metrics;
+use crate::selector::MatchMode;
+use crate::state::StateStore;
+use crate::{
+    compile_watches, daemon_name, find_missing_targets, health, list_targets, run_until, scan_once,
+    telemetry, watches_on, Dockers, MissingTarget, Settings, Watch,
+};
+use bollard::{Docker, API_DEFAULT_VERSION};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use log::{debug, error, info, warn};
+use std::env;
+use std::future::{pending, Future};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::signal;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{sleep, Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[command(
+    author = "Zachary Williams",
+    about = "Monitor containers and restart on log patterns."
+)]
+pub(crate) struct Args {
+    /// YAML, TOML or JSON file describing the containers to watch (replaces the per-watch flags)
+    #[arg(long, short, value_name = "PATH", conflicts_with_all = ["watch", "watch_label", "watch_image", "watch_all", "restart", "pattern", "threshold", "window", "stream", "exclude_pattern", "restart_compose_project", "on_match_command", "command_mode", "signal", "invert_restart", "expect_pattern", "expect_within", "on_event", "pattern_file", "pre_restart_command", "post_restart_command", "pre_restart_gate"])]
+    config: Option<PathBuf>,
+
+    /// Containers to watch (comma-separated names share the flags of this --watch)
+    #[arg(long, short, value_name = "CONTAINER", required_unless_present_any = ["config", "watch_label", "watch_image", "watch_all"], action = clap::ArgAction::Append)]
+    watch: Vec<String>,
+
+    /// Watch every container carrying this label (ordered alongside --watch)
+    #[arg(long, value_name = "KEY=VALUE", action = clap::ArgAction::Append)]
+    watch_label: Vec<String>,
+
+    /// Watch every container running this image (ordered alongside --watch)
+    #[arg(long, value_name = "IMAGE", action = clap::ArgAction::Append)]
+    watch_image: Vec<String>,
+
+    /// Watch every running container, same as --watch '*' (ordered alongside --watch)
+    #[arg(long)]
+    watch_all: bool,
+
+    /// Containers to restart (comma-separated)
+    #[arg(long, short, value_name = "CONTAINER", required_unless_present = "config", action = clap::ArgAction::Append)]
+    restart: Vec<String>,
+
+    /// Patterns to watch for (comma-separated)
+    #[arg(long, short, value_name = "PATTERN", required_unless_present_any = ["config", "pattern_file"], action = clap::ArgAction::Append)]
+    pattern: Vec<String>,
+
+    /// File with one pattern per line to add to --pattern, ignoring blank lines and # comments ('' for none)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    pattern_file: Vec<String>,
+
+    /// Number of occurrences before a restart is triggered (defaults to 1)
+    #[arg(long, short, visible_alias = "count", value_name = "COUNT", value_parser = clap::value_parser!(u32).range(1..), action = clap::ArgAction::Append)]
+    threshold: Vec<u32>,
+
+    /// Only count occurrences within this long toward --threshold (0 counts all)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_secs::<u64>, action = clap::ArgAction::Append)]
+    window: Vec<u64>,
+
+    /// Patterns that suppress a match when also present in the line (comma-separated)
+    #[arg(long, value_name = "PATTERN", action = clap::ArgAction::Append)]
+    exclude_pattern: Vec<String>,
+
+    /// Restart the watched container's whole compose project instead of --restart (defaults to false)
+    #[arg(long, value_name = "BOOLEAN", action = clap::ArgAction::Append)]
+    restart_compose_project: Vec<bool>,
+
+    /// Shell command to run when the threshold is reached, with WATCH_CONTAINER, MATCHED_PATTERN and MATCHED_LINE set
+    #[arg(long, value_name = "CMD", action = clap::ArgAction::Append)]
+    on_match_command: Vec<String>,
+
+    /// Run --on-match-command in addition to or instead of restarting (defaults to also)
+    #[arg(long, value_name = "MODE", value_enum, action = clap::ArgAction::Append)]
+    command_mode: Vec<CommandMode>,
+
+    /// Send this signal to the restart targets instead of restarting them, e.g. SIGHUP
+    #[arg(long, value_name = "SIGNAL", action = clap::ArgAction::Append)]
+    signal: Vec<String>,
+
+    /// Stop the restart targets instead of restarting them, e.g. to leave it to an orchestrator (defaults to false)
+    #[arg(long, value_name = "BOOLEAN", action = clap::ArgAction::Append)]
+    invert_restart: Vec<bool>,
+
+    /// Shell command to run before restarting each target, e.g. to capture a heap dump ('' for none)
+    #[arg(long, value_name = "COMMAND", action = clap::ArgAction::Append)]
+    pre_restart_command: Vec<String>,
+
+    /// Shell command to run after each target was restarted, e.g. to warm a cache ('' for none)
+    #[arg(long, value_name = "COMMAND", action = clap::ArgAction::Append)]
+    post_restart_command: Vec<String>,
+
+    /// Don't restart a target when --pre-restart-command fails for it (defaults to false)
+    #[arg(long, value_name = "BOOLEAN", action = clap::ArgAction::Append)]
+    pre_restart_gate: Vec<bool>,
+
+    /// Output stream to match patterns against (defaults to both)
+    #[arg(long, value_name = "STREAM", value_enum, action = clap::ArgAction::Append)]
+    stream: Vec<Stream>,
+
+    /// Restart the targets when this pattern hasn't been logged for --expect-within
+    #[arg(long, value_name = "PATTERN", requires = "expect_within", action = clap::ArgAction::Append)]
+    expect_pattern: Vec<String>,
+
+    /// How long --expect-pattern may go unseen before restarting (0 disables it for that watch)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_secs::<u64>, requires = "expect_pattern", action = clap::ArgAction::Append)]
+    expect_within: Vec<u64>,
+
+    /// Docker events of the watched container that trigger a restart, e.g. die or "health_status: unhealthy" (comma-separated)
+    #[arg(long, value_name = "EVENT", action = clap::ArgAction::Append)]
+    on_event: Vec<String>,
+
+    /// Ignore further matches for this long after a counted one, so a multi-line trace counts once (a bare number is milliseconds)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_millis)]
+    debounce: Option<Duration>,
+
+    /// Log but don't act on matches for this long after monitoring of a container begins
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    startup_grace: Option<Duration>,
+
+    /// How --watch names are compared to container names, e.g. prefix to match compose's `project-service-1`
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = MatchMode::Exact)]
+    match_mode: MatchMode,
+
+    /// Interpret patterns as regular expressions instead of plain substrings
+    #[arg(long)]
+    regex: bool,
+
+    /// Only match against the first BYTES of each log line, protecting against huge lines
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    max_line_bytes: Option<u64>,
+
+    /// Remove ANSI escape codes (colors etc.) from log lines before matching
+    #[arg(long)]
+    strip_ansi: bool,
+
+    /// Ignore case when matching patterns and exclude patterns
+    #[arg(long)]
+    case_insensitive: bool,
+
+    /// Require the trimmed log line to equal a pattern instead of containing it (regexes are anchored)
+    #[arg(long)]
+    match_whole_line: bool,
+
+    /// Time between container discovery polls
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = duration::parse_positive_duration)]
+    poll_interval: Duration,
+
+    /// Randomize each discovery poll by up to ± this long, spreading load across monitors
+    #[arg(long, value_name = "DURATION", default_value = "0s", value_parser = duration::parse_duration)]
+    poll_jitter: Duration,
+
+    /// How long to wait before retrying after a failed Docker API call
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = duration::parse_positive_duration)]
+    reconnect_delay: Duration,
+
+    /// Upper bound for the doubling retry delay after consecutive failures
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = duration::parse_positive_duration)]
+    max_reconnect_delay: Duration,
+
+    /// How long to wait before re-subscribing to a log stream that ended
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = duration::parse_positive_duration)]
+    resubscribe_delay: Duration,
+
+    /// Drop and re-subscribe to a container's logs when no line arrived for this long
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_positive_duration)]
+    log_idle_timeout: Option<Duration>,
+
+    /// Reopen a daemon's log streams when none of its watched containers logged a line for this long
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_positive_duration)]
+    stall_timeout: Option<Duration>,
+
+    /// How much of the existing logs to scan when monitoring of a container starts
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = duration::parse_duration)]
+    log_lookback: Duration,
+
+    /// Scan the last N lines instead of --log-lookback when monitoring of a container starts
+    #[arg(long, value_name = "N")]
+    tail: Option<u64>,
+
+    /// Read the watched containers' existing logs once, restart targets whose thresholds are met, then exit
+    #[arg(long, conflicts_with_all = ["list", "once", "startup_grace"])]
+    scan_once: bool,
+
+    /// Ping Docker every poll and reopen all log streams once it is reachable again after a failure
+    #[arg(long = "restart-self-on-docker-reconnect")]
+    resubscribe_on_reconnect: bool,
+
+    /// Minimum time between restarts triggered by the same watch
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    cooldown: Option<Duration>,
+
+    /// Apply the cooldown to each pattern of a watch separately, so one pattern doesn't hold back another
+    #[arg(long)]
+    pattern_cooldown_per_pattern: bool,
+
+    /// Stop restarting for a watch once it has triggered this many restarts
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u32).range(1..))]
+    max_restarts: Option<u32>,
+
+    /// Reset a watch's restart count once this long passes without a restart
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration, requires = "max_restarts")]
+    restart_window: Option<Duration>,
+
+    /// Restart each watch's targets at most once per run, still logging later matches
+    #[arg(long)]
+    restart_on_first_match_only: bool,
+
+    /// Escalate when a restart's pattern matches again within this long of it, since the restart didn't help
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_positive_duration)]
+    verify_window: Option<Duration>,
+
+    /// Docker daemon to connect to, e.g. tcp://10.0.0.2:2375 (defaults to the local socket, or named pipe on Windows)
+    #[arg(long, value_name = "URL", env = "DOCKER_HOST")]
+    docker_host: Option<String>,
+
+    /// Connect to the daemon of this Docker CLI context (see `docker context ls`)
+    #[arg(long, value_name = "NAME")]
+    docker_context: Option<String>,
+
+    /// Retry connecting to Docker at startup this many times before giving up, for daemons still booting
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    connect_retries: u32,
+
+    /// Delay before the first startup connection retry, doubling up to --max-reconnect-delay
+    #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = duration::parse_positive_duration)]
+    connect_retry_delay: Duration,
+
+    /// Client certificate for TLS connections to the Docker host
+    #[arg(long, value_name = "PATH", requires_all = ["tls_key", "tls_ca", "docker_host"])]
+    tls_cert: Option<PathBuf>,
+
+    /// Client private key for TLS connections to the Docker host
+    #[arg(long, value_name = "PATH", requires_all = ["tls_cert", "tls_ca", "docker_host"])]
+    tls_key: Option<PathBuf>,
+
+    /// Certificate authority for TLS connections to the Docker host
+    #[arg(long, value_name = "PATH", requires_all = ["tls_cert", "tls_key", "docker_host"])]
+    tls_ca: Option<PathBuf>,
+
+    /// Log output format
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Don't color text logs, which are otherwise colored when written to a terminal (also set by NO_COLOR)
+    #[arg(long)]
+    no_color: bool,
+
+    /// Log more: -v for info, -vv for debug, -vvv for trace (overrides RUST_LOG)
+    #[arg(long, short, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only log errors rather than warnings and errors (overrides RUST_LOG)
+    #[arg(long, short)]
+    quiet: bool,
+
+    /// URL to POST a JSON event to after each restart
+    #[arg(long, value_name = "URL")]
+    webhook_url: Option<String>,
+
+    /// Slack incoming webhook URL to post a message to after every restart
+    #[arg(long, value_name = "URL")]
+    slack_webhook: Option<String>,
+
+    /// Export a trace span per restart to this OTLP/HTTP collector, e.g. http://collector:4318/v1/traces
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Maximum restarts across all watches in any rolling minute
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u32).range(1..))]
+    max_restarts_per_minute: Option<u32>,
+
+    /// Maximum containers being restarted at once across all watches (unlimited by default)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_concurrent_restarts: Option<u32>,
+
+    /// How long Docker waits for a container to stop before killing it on restart, in whole seconds (defaults to Docker's own timeout)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_secs::<u32>)]
+    restart_timeout: Option<u32>,
+
+    /// How long to wait between restarting consecutive targets of a watch
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    restart_delay: Option<Duration>,
+
+    /// Retry a failed restart of a target this many times, doubling the delay from 1s each time
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    restart_retries: u32,
+
+    /// Allow the monitor to watch and restart its own container
+    #[arg(long)]
+    allow_self: bool,
+
+    /// JSON file to persist restart counts and times in across monitor restarts
+    #[arg(long, value_name = "PATH")]
+    state_file: Option<PathBuf>,
+
+    /// Also start restart targets that exist but are stopped
+    #[arg(long)]
+    start_stopped: bool,
+
+    /// Container states that count as running when watching and restarting (comma-separated)
+    #[arg(
+        long,
+        value_name = "STATUS",
+        value_delimiter = ',',
+        default_value = "running,restarting",
+        value_parser = ["created", "running", "restarting", "paused", "removing", "exited", "dead"]
+    )]
+    container_status: Vec<String>,
+
+    /// Append a JSON line describing every restart to this file
+    #[arg(long, value_name = "PATH")]
+    audit_file: Option<PathBuf>,
+
+    /// Log the restarts that would happen without performing them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Exit instead of warning when a watch or restart target matches no running container at startup
+    #[arg(long)]
+    strict: bool,
+
+    /// Exit if a watched container is not running at startup, rather than waiting for it to appear
+    #[arg(long)]
+    require_all_at_startup: bool,
+
+    /// URL to GET after every successful container poll, for a dead man's switch like Healthchecks.io
+    #[arg(long, value_name = "URL")]
+    heartbeat_url: Option<String>,
+
+    /// Serve GET /healthz on this port, returning 503 once container polling has stalled, plus GET /history and /metrics
+    #[arg(long, value_name = "PORT")]
+    health_port: Option<u16>,
+
+    /// Log how long each watched container has gone without a restart this often
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_positive_duration)]
+    uptime_report_interval: Option<Duration>,
+
+    /// Log how many lines each watched container logged, matched and restarted for this often, and on shutdown
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_positive_duration)]
+    match_stats: Option<Duration>,
+
+    /// Number of recent restarts GET /history returns
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    history_size: usize,
+
+    /// Print the containers the watch and restart selectors currently resolve to, then exit
+    #[arg(long)]
+    list: bool,
+
+    /// Validate the config file (or per-watch flags) without connecting to Docker, then exit
+    #[arg(long, conflicts_with = "list")]
+    config_check: bool,
+
+    /// Exit after the first successful restart
+    #[arg(long)]
+    once: bool,
+}
+
+impl Args {
+    /// Every flag at its default, for running without a command line. The config path
+    /// is only there to satisfy the required flags and is never read.
+    pub(crate) fn defaults() -> Self {
+        Args::parse_from([env!("CARGO_PKG_NAME"), "--config", "-"])
+    }
+
+    fn tls_files(&self) -> Option<TlsFiles> {
+        match (&self.tls_cert, &self.tls_key, &self.tls_ca) {
+            (Some(cert), Some(key), Some(ca)) => Some(TlsFiles {
+                cert: cert.clone(),
+                key: key.clone(),
+                ca: ca.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn match_options(&self) -> MatchOptions {
+        MatchOptions {
+            regex: self.regex,
+            case_insensitive: self.case_insensitive,
+            whole_line: self.match_whole_line,
+        }
+    }
+}
+
+impl Settings {
+    pub(crate) fn new(
+        args: &Args,
+        state: Option<StateStore>,
+        audit: Option<AuditLog>,
+        once: Option<mpsc::Sender<()>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Settings {
+            poll_interval: args.poll_interval,
+            poll_jitter: args.poll_jitter,
+            reconnect_delay: args.reconnect_delay,
+            max_reconnect_delay: args.max_reconnect_delay,
+            resubscribe_delay: args.resubscribe_delay,
+            log_lookback: args.log_lookback,
+            resubscribe_on_reconnect: args.resubscribe_on_reconnect,
+            tail: args.tail,
+            scan_once: args.scan_once,
+            strip_ansi: args.strip_ansi,
+            max_line_bytes: args.max_line_bytes.map(|max| max as usize),
+            cooldown: args.cooldown,
+            cooldown_per_pattern: args.pattern_cooldown_per_pattern,
+            debounce: args.debounce,
+            startup_grace: args.startup_grace,
+            log_idle_timeout: args.log_idle_timeout,
+            stall_timeout: args.stall_timeout,
+            uptime_report_interval: args.uptime_report_interval,
+            match_stats_interval: args.match_stats,
+            max_restarts: args.max_restarts,
+            restart_window: args.restart_window,
+            first_match_only: args.restart_on_first_match_only,
+            verify_window: args.verify_window,
+            restart_permits: args
+                .max_concurrent_restarts
+                .map(|permits| Semaphore::new(permits as usize)),
+            rate_limiter: args
+                .max_restarts_per_minute
+                .map(|limit| RateLimiter::new(limit, Duration::from_secs(60))),
+            webhook_url: args.webhook_url.clone(),
+            slack_webhook: args.slack_webhook.clone(),
+            recent_restarts: Throttle::new(RESTART_DEDUPE_WINDOW),
+            slack_throttle: Throttle::new(args.cooldown.unwrap_or(SLACK_MIN_INTERVAL)),
+            restart_timeout: args.restart_timeout,
+            restart_delay: args.restart_delay,
+            restart_retries: args.restart_retries,
+            start_stopped: args.start_stopped,
+            container_statuses: args.container_status.clone(),
+            dry_run: args.dry_run,
+            self_id: match args.allow_self {
+                true => None,
+                false => identity::own_container_id(),
+            },
+            self_warned: AtomicBool::new(false),
+            state,
+            audit,
+            once,
+            metrics: Metrics::default(),
+            on_restart: None,
+            health: Health::new(args.poll_interval * HEALTH_STALE_POLLS),
+            history: History::new(args.history_size),
+            http: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()?,
+        })
+    }
+}
+
+/// Parses the command line and monitors until a shutdown signal (or `--once`).
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    logging::init(
+        args.log_format,
+        logging::level(args.verbose, args.quiet),
+        !args.no_color,
+    );
+
+    debug!("Raw clap args: {args:?}");
+
+    let tracer = match telemetry::init(args.otlp_endpoint.as_deref()) {
+        Ok(tracer) => tracer,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let configs = match build_configs(&args, &matches) {
+        Ok(configs) => configs,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    debug!("Parsed configs: {configs:?}");
+
+    let watches = match compile_watches(configs, args.match_options(), args.match_mode) {
+        Ok(watches) => watches,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    if args.config_check {
+        println!("Configuration is valid: {} watch(es)", watches.len());
+        return Ok(());
+    }
+
+    let state = match args.state_file.as_deref().map(StateStore::load).transpose() {
+        Ok(state) => state,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let audit = match args.audit_file.as_deref().map(AuditLog::open).transpose() {
+        Ok(audit) => audit,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
+    };
+
+    let dockers = match connect_all(&args, &watches).await {
+        Ok(dockers) => dockers,
+        Err(err) => {
+            error!("Failed to connect to Docker with error: {err}");
+            exit(1);
+        }
+    };
+
+    for (host, docker) in &dockers {
+        let host_watches = watches_on(&watches, host);
+        match find_missing_targets(docker, &args.container_status, &host_watches).await {
+            Ok(missing) => {
+                let fatal = |target: &MissingTarget| {
+                    args.strict || (args.require_all_at_startup && target.watched)
+                };
+                for target in &missing {
+                    match fatal(target) {
+                        true => error!("{}", target.message),
+                        false => warn!("{}", target.message),
+                    }
+                }
+                if missing.iter().any(fatal) {
+                    exit(1);
+                }
+            }
+            Err(err) if args.strict || args.require_all_at_startup => {
+                error!(
+                    "Failed to validate watch and restart targets on {}: {err}",
+                    daemon_name(host)
+                );
+                exit(1);
+            }
+            Err(err) => warn!(
+                "Failed to validate watch and restart targets on {}: {err}",
+                daemon_name(host)
+            ),
+        }
+    }
+
+    let (once_tx, mut once_rx) = mpsc::channel(1);
+
+    let settings = Arc::new(Settings::new(
+        &args,
+        state,
+        audit,
+        args.once.then_some(once_tx),
+    )?);
+
+    if args.list {
+        let mut hosts: Vec<_> = dockers.iter().collect();
+        hosts.sort_by_key(|(host, _)| *host);
+        for (host, docker) in hosts {
+            if dockers.len() > 1 {
+                println!("{}:", daemon_name(host));
+            }
+            if let Err(err) = list_targets(docker, &settings, &watches_on(&watches, host)).await {
+                error!("Failed to list containers on {}: {err}", daemon_name(host));
+                exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(port) = args.health_port {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to listen on health port {port}: {err}");
+                exit(1);
+            }
+        };
+        let health_settings = Arc::clone(&settings);
+        tokio::spawn(health::serve(listener, move |path| match path {
+            "/healthz" => Some(health_settings.health.reply()),
+            "/history" => Some(Reply::json(health_settings.history.to_json())),
+            "/metrics" => Some(Reply::json(health_settings.metrics.to_json())),
+            _ => None,
+        }));
+        info!("Serving health checks on port {port}");
+    }
+
+    if let Some(url) = args.heartbeat_url.clone() {
+        let heartbeat_settings = Arc::clone(&settings);
+        tokio::spawn(async move {
+            health::heartbeat(&heartbeat_settings.health, &heartbeat_settings.http, &url).await;
+        });
+        info!("Pinging the heartbeat URL after every poll");
+    }
+
+    if args.scan_once {
+        info!("Connected to Docker. Scanning logs once...");
+        let scans = dockers.iter().map(|(host, docker)| {
+            let (settings, watches) = (&settings, watches_on(&watches, host));
+            async move { (host, scan_once(docker, settings, &watches).await) }
+        });
+        let mut failed = false;
+        for (host, result) in futures_util::future::join_all(scans).await {
+            if let Err(err) = result {
+                error!("Failed to get containers from {}: {err}", daemon_name(host));
+                failed = true;
+            }
+        }
+        // Once for all daemons, like the monitor does
+        if settings.match_stats_interval.is_some() {
+            settings.metrics.report_match_stats();
+        }
+        if failed {
+            exit(1);
+        }
+    } else {
+        info!("Connected to Docker. Beginning to monitor logs...");
+
+        let (reload_tx, reloads) = tokio::sync::watch::channel(Arc::new(watches));
+        tokio::spawn(reload_on_hangup(
+            args.config.clone(),
+            args.match_options(),
+            args.match_mode,
+            dockers.keys().cloned().collect(),
+            reload_tx,
+        ));
+
+        let shutdown = async {
+            tokio::select! {
+                _ = shutdown_signal() => {}
+                Some(()) = once_rx.recv() => info!("Restart completed and --once was given"),
+            }
+        };
+        run_until(&dockers, reloads, &settings, shutdown).await;
+    }
+
+    settings.metrics.summary(started.elapsed());
+
+    if let Some(tracer) = tracer {
+        if let Err(err) = tracer.shutdown() {
+            warn!("Failed to flush trace spans: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-reads `--config` on SIGHUP and hands the new watches to `monitor_containers`. Only
+/// the daemons in `hosts` were connected to at startup, watches on others are ignored.
+async fn reload_on_hangup(
+    config: Option<PathBuf>,
+    options: MatchOptions,
+    match_mode: MatchMode,
+    hosts: Vec<Option<String>>,
+    reloads: tokio::sync::watch::Sender<Arc<Vec<Watch>>>,
+) {
+    #[cfg(unix)]
+    {
+        let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                error!("Failed to listen for SIGHUP: {err}");
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            let Some(path) = &config else {
+                warn!("Received SIGHUP, but only watches from --config can be reloaded");
+                continue;
+            };
+
+            let watches = load_config_file(path).and_then(|configs| {
+                validate_configs(&configs)?;
+                compile_watches(configs, options, match_mode)
+            });
+            match watches {
+                Ok(watches) => {
+                    info!(
+                        "Reloaded {} watch(es) from '{}'",
+                        watches.len(),
+                        path.display()
+                    );
+                    for watch in watches
+                        .iter()
+                        .filter(|watch| !hosts.contains(&watch.config.docker_host))
+                    {
+                        warn!(
+                            "[{}] {} wasn't connected to at startup, restart the monitor to watch it",
+                            watch.config.watch,
+                            daemon_name(&watch.config.docker_host)
+                        );
+                    }
+                    reloads.send_replace(Arc::new(watches));
+                }
+                Err(err) => error!("Failed to reload config, keeping the current watches: {err}"),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = (config, options, match_mode, hosts, reloads);
+}
+
+/// Resolves once the process receives SIGINT (Ctrl+C) or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {err}");
+            pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                error!("Failed to listen for SIGTERM: {err}");
+                pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+pub(crate) fn build_configs(
+    args: &Args,
+    matches: &ArgMatches,
+) -> Result<Vec<ContainerRestartConfig>, Box<dyn std::error::Error>> {
+    let configs = match &args.config {
+        Some(path) => load_config_file(path)?,
+        None => build_cli_configs(args, &cli_watches(args, matches))?,
+    };
+
+    validate_configs(&configs)?;
+
+    Ok(configs)
+}
+
+/// Merges `--watch` and `--watch-label` into a single list, in the order they
+/// were given on the command line, so they line up with `--restart` and `--pattern`.
+fn cli_watches(args: &Args, matches: &ArgMatches) -> Vec<String> {
+    let names = matches
+        .indices_of("watch")
+        .into_iter()
+        .flatten()
+        .zip(args.watch.iter().cloned());
+    let labels = matches.indices_of("watch_label").into_iter().flatten().zip(
+        args.watch_label
+            .iter()
+            .map(|label| format!("label:{label}")),
+    );
+    let images = matches.indices_of("watch_image").into_iter().flatten().zip(
+        args.watch_image
+            .iter()
+            .map(|image| format!("image:{image}")),
+    );
+
+    // The flag's default value still has an index, so only look at it when set
+    let all = matches
+        .indices_of("watch_all")
+        .into_iter()
+        .flatten()
+        .filter(|_| args.watch_all)
+        .take(1)
+        .map(|index| (index, "*".to_string()));
+
+    let mut watches: Vec<(usize, String)> = names.chain(labels).chain(images).chain(all).collect();
+    watches.sort_by_key(|(index, _)| *index);
+
+    watches.into_iter().map(|(_, watch)| watch).collect()
+}
+
+pub(crate) fn build_cli_configs(
+    args: &Args,
+    watches: &[String],
+) -> Result<Vec<ContainerRestartConfig>, Box<dyn std::error::Error>> {
+    let watch_count = watches.len();
+
+    // --pattern may be left out entirely when every watch reads its patterns from a file
+    let patterns = match args.pattern.is_empty() && !args.pattern_file.is_empty() {
+        true => vec![String::new(); watch_count],
+        false => args.pattern.clone(),
+    };
+
+    if (watch_count != args.restart.len()) || (watch_count != patterns.len()) {
+        return Err("Invalid args. Expected format: '--watch <container> --restart [container] --pattern [pattern]'.\nThe number of --watch (including --watch-label, --watch-image and --watch-all), --restart and --pattern should be symmetrical.".into());
+    }
+
+    let thresholds = per_watch(&args.threshold, watch_count, 1, "--threshold")?;
+    let windows = per_watch(&args.window, watch_count, 0, "--window")?;
+    let streams = per_watch(&args.stream, watch_count, Stream::Both, "--stream")?;
+    let compose = per_watch(
+        &args.restart_compose_project,
+        watch_count,
+        false,
+        "--restart-compose-project",
+    )?;
+    let excludes = per_watch(
+        &args.exclude_pattern,
+        watch_count,
+        String::new(),
+        "--exclude-pattern",
+    )?;
+    let commands = per_watch(
+        &args.on_match_command,
+        watch_count,
+        String::new(),
+        "--on-match-command",
+    )?;
+    let signals = per_watch(&args.signal, watch_count, String::new(), "--signal")?;
+    let inverts = per_watch(&args.invert_restart, watch_count, false, "--invert-restart")?;
+    let pre_commands = per_watch(
+        &args.pre_restart_command,
+        watch_count,
+        String::new(),
+        "--pre-restart-command",
+    )?;
+    let post_commands = per_watch(
+        &args.post_restart_command,
+        watch_count,
+        String::new(),
+        "--post-restart-command",
+    )?;
+    let gates = per_watch(
+        &args.pre_restart_gate,
+        watch_count,
+        false,
+        "--pre-restart-gate",
+    )?;
+    let command_modes = per_watch(
+        &args.command_mode,
+        watch_count,
+        CommandMode::Also,
+        "--command-mode",
+    )?;
+    let expect_patterns = per_watch(
+        &args.expect_pattern,
+        watch_count,
+        String::new(),
+        "--expect-pattern",
+    )?;
+    let expect_withins = per_watch(&args.expect_within, watch_count, 0, "--expect-within")?;
+    let events = per_watch(&args.on_event, watch_count, String::new(), "--on-event")?;
+    let pattern_files = per_watch(
+        &args.pattern_file,
+        watch_count,
+        String::new(),
+        "--pattern-file",
+    )?;
+
+    // Regexes can contain commas of their own (`\d{1,3}`), so each value is one pattern
+    let split_patterns = |value: &str| -> Vec<String> {
+        match args.regex {
+            true => Some(value.to_string())
+                .filter(|pattern| !pattern.is_empty())
+                .into_iter()
+                .collect(),
+            false => value
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    };
+
+    let mut configs: Vec<ContainerRestartConfig> = (0..watch_count)
+        .map(|i| {
+            let restart: Vec<String> = args.restart[i]
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect();
+
+            ContainerRestartConfig {
+                watch: watches[i].to_string(),
+                restart,
+                pattern: split_patterns(&patterns[i]),
+                threshold: thresholds[i],
+                window: Some(windows[i]),
+                stream: streams[i],
+                exclude_pattern: split_patterns(&excludes[i]),
+                restart_compose_project: compose[i],
+                on_match_command: Some(commands[i].clone()).filter(|c| !c.is_empty()),
+                command_mode: command_modes[i],
+                signal: Some(signals[i].clone()).filter(|signal| !signal.is_empty()),
+                invert_restart: inverts[i],
+                // The global flags apply to every watch built from the CLI
+                restart_timeout: None,
+                restart_delay: None,
+                cooldown: None,
+                max_restarts: None,
+                docker_host: None,
+                // An empty pattern or 0 seconds turns the check off for this watch
+                expect_pattern: Some(expect_patterns[i].clone())
+                    .filter(|pattern| !pattern.is_empty() && expect_withins[i] > 0),
+                expect_within: Some(expect_withins[i])
+                    .filter(|within| *within > 0 && !expect_patterns[i].is_empty()),
+                on_event: events[i]
+                    .split(',')
+                    .map(|event| event.trim())
+                    .filter(|event| !event.is_empty())
+                    .map(|event| event.to_string())
+                    .collect(),
+                pre_restart_command: Some(pre_commands[i].clone()).filter(|c| !c.is_empty()),
+                post_restart_command: Some(post_commands[i].clone()).filter(|c| !c.is_empty()),
+                pre_restart_gate: gates[i],
+            }
+        })
+        .collect();
+
+    for (config, path) in configs.iter_mut().zip(&pattern_files) {
+        if !path.is_empty() {
+            config.pattern.extend(load_pattern_file(Path::new(path))?);
+        }
+    }
+
+    // `--watch api,worker` is shorthand for one watch per name with the same flags. Name
+    // regexes can contain commas of their own, so they are left as they are
+    let split_names = args.match_mode != MatchMode::Regex;
+    let mut configs: Vec<ContainerRestartConfig> = configs
+        .into_iter()
+        .flat_map(|config| {
+            let names: Vec<String> = config
+                .watch
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+            match split_names && names.len() > 1 && args.watch.contains(&config.watch) {
+                true => names
+                    .into_iter()
+                    .map(|watch| ContainerRestartConfig {
+                        watch,
+                        ..config.clone()
+                    })
+                    .collect(),
+                false => vec![config],
+            }
+        })
+        .collect();
+
+    for config in &mut configs {
+        expand_env_vars(config).map_err(|err| format!("Invalid args. {err}"))?;
+    }
+
+    Ok(configs)
+}
+
+const DOCKER_TIMEOUT_SECS: u64 = 120;
+/// Polls that may be missed before /healthz reports unhealthy.
+const HEALTH_STALE_POLLS: u32 = 3;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long after a restart other triggers skip the same container.
+const RESTART_DEDUPE_WINDOW: Duration = Duration::from_secs(10);
+/// How long identical Slack messages are suppressed for when no --cooldown is set.
+const SLACK_MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Tries `--docker-context`, `--docker-host`, then `DOCKER_HOST`, then the local socket (or
+/// named pipe on Windows), returning the first daemon that answers a ping.
+async fn connect_docker(args: &Args) -> Result<Docker, Box<dyn std::error::Error>> {
+    let mut attempts: Vec<(String, Result<Docker, String>)> = Vec::new();
+    let tls = args.tls_files();
+
+    if let Some(name) = &args.docker_context {
+        match context::resolve(name) {
+            Ok(Some(context)) => {
+                let docker = connect_docker_host(&context.host, context.tls.as_ref())
+                    .map_err(|err| err.to_string());
+                attempts.push((format!("context '{name}' ({})", context.host), docker));
+            }
+            Ok(None) => warn!("Docker context '{name}' not found, falling back to the default connection"),
+            Err(err) => warn!("Failed to read Docker context '{name}', falling back to the default connection: {err}"),
+        }
+    }
+    if let Some(host) = &args.docker_host {
+        let docker = connect_docker_host(host, tls.as_ref()).map_err(|err| err.to_string());
+        attempts.push((host.clone(), docker));
+    }
+    if let Ok(host) = env::var("DOCKER_HOST") {
+        if args.docker_host.as_ref() != Some(&host) {
+            let docker = connect_docker_host(&host, tls.as_ref()).map_err(|err| err.to_string());
+            attempts.push((host, docker));
+        }
+    }
+    // `connect_with_socket_defaults` checks that the path exists first, which named pipes
+    // don't reliably pass, so Windows goes through the pipe connector directly
+    #[cfg(windows)]
+    attempts.push((
+        "the local named pipe".to_string(),
+        Docker::connect_with_named_pipe_defaults().map_err(|err| err.to_string()),
+    ));
+    #[cfg(not(windows))]
+    attempts.push((
+        "the local socket".to_string(),
+        Docker::connect_with_socket_defaults().map_err(|err| err.to_string()),
+    ));
+
+    let mut failures = Vec::new();
+    for (target, attempt) in attempts {
+        let reason = match attempt {
+            Ok(docker) => match docker.ping().await {
+                Ok(_) => {
+                    debug!("Connected to Docker via {target}");
+                    return Ok(docker);
+                }
+                Err(err) => err.to_string(),
+            },
+            Err(err) => err,
+        };
+
+        debug!("Could not connect to Docker via {target}: {reason}");
+        failures.push(format!("{target} ({reason})"));
+    }
+
+    Err(format!(
+        "no Docker daemon reachable via {}. Is Docker running?",
+        failures.join(", ")
+    )
+    .into())
+}
+
+/// Connects to the default daemon if any watch is on it, and to every `docker_host` the
+/// watches name. Only the default daemon has to answer at startup: the others are retried
+/// by their monitoring like a lost connection, so one being down doesn't stop the rest.
+async fn connect_all(
+    args: &Args,
+    watches: &[Watch],
+) -> Result<Dockers, Box<dyn std::error::Error>> {
+    let mut dockers = Dockers::new();
+    if watches
+        .iter()
+        .any(|watch| watch.config.docker_host.is_none())
+    {
+        dockers.insert(
+            None,
+            connect_with_retries(args, || connect_docker(args)).await?,
+        );
+    }
+
+    let tls = args.tls_files();
+    for host in watches
+        .iter()
+        .filter_map(|watch| watch.config.docker_host.clone())
+    {
+        if dockers.contains_key(&Some(host.clone())) {
+            continue;
+        }
+
+        let docker =
+            connect_docker_host(&host, tls.as_ref()).map_err(|err| format!("{host}: {err}"))?;
+        match docker.ping().await {
+            Ok(_) => debug!("Connected to Docker at {host}"),
+            Err(err) => warn!("Docker at {host} is not reachable yet, will keep trying: {err}"),
+        }
+        dockers.insert(Some(host), docker);
+    }
+
+    Ok(dockers)
+}
+
+/// `connect`, retried `--connect-retries` times so the monitor can start before the
+/// daemon is up.
+pub(crate) async fn connect_with_retries<T, F>(
+    args: &Args,
+    mut connect: impl FnMut() -> F,
+) -> Result<T, Box<dyn std::error::Error>>
+where
+    F: Future<Output = Result<T, Box<dyn std::error::Error>>>,
+{
+    let mut delay = args.connect_retry_delay;
+    let mut attempt = 0;
+
+    loop {
+        match connect().await {
+            Ok(docker) => return Ok(docker),
+            Err(err) if attempt >= args.connect_retries => return Err(err),
+            Err(err) => {
+                attempt += 1;
+                warn!(
+                    "Failed to connect to Docker, retrying in {}s ({attempt}/{}): {err}",
+                    delay.as_secs_f32(),
+                    args.connect_retries
+                );
+                sleep(delay).await;
+                delay = (delay * 2).min(args.max_reconnect_delay);
+            }
+        }
+    }
+}
+
+pub(crate) fn connect_docker_host(
+    host: &str,
+    tls: Option<&TlsFiles>,
+) -> Result<Docker, Box<dyn std::error::Error>> {
+    debug!("Connecting to Docker host: {host}");
+
+    if let Some(tls) = tls {
+        return Ok(Docker::connect_with_ssl(
+            host,
+            &tls.key,
+            &tls.cert,
+            &tls.ca,
+            DOCKER_TIMEOUT_SECS,
+            API_DEFAULT_VERSION,
+        )?);
+    }
+
+    let docker = match host {
+        h if h.starts_with("unix://") => {
+            Docker::connect_with_socket(h, DOCKER_TIMEOUT_SECS, API_DEFAULT_VERSION)?
+        }
+        h if h.starts_with("npipe://") => connect_named_pipe(h)?,
+        h if h.starts_with("tcp://") || h.starts_with("http://") => {
+            Docker::connect_with_http(h, DOCKER_TIMEOUT_SECS, API_DEFAULT_VERSION)?
+        }
+        h => {
+            return Err(format!(
+            "Unsupported Docker host '{h}'. Expected a unix://, npipe://, tcp:// or http:// URL"
+        )
+            .into())
+        }
+    };
+
+    Ok(docker)
+}
+
+#[cfg(windows)]
+fn connect_named_pipe(pipe: &str) -> Result<Docker, Box<dyn std::error::Error>> {
+    Ok(Docker::connect_with_named_pipe(
+        pipe,
+        DOCKER_TIMEOUT_SECS,
+        API_DEFAULT_VERSION,
+    )?)
+}
+
+#[cfg(not(windows))]
+fn connect_named_pipe(pipe: &str) -> Result<Docker, Box<dyn std::error::Error>> {
+    Err(format!("'{pipe}' is a named pipe, which is only supported on Windows").into())
+}
+
+/// Expands an optional per-watch flag. When omitted every watch gets `default`,
+/// otherwise the flag must be given once per `--watch`.
+fn per_watch<T: Clone>(
+    values: &[T],
+    watch_count: usize,
+    default: T,
+    flag: &str,
+) -> Result<Vec<T>, String> {
+    match values.len() {
+        0 => Ok(vec![default; watch_count]),
+        n if n == watch_count => Ok(values.to_vec()),
+        n => Err(format!("Invalid args. {flag} was given {n} time(s) for {watch_count} --watch value(s).\n{flag} is optional, but when given it must be given once per --watch.")),
+    }
+}
@@ -0,0 +1,89 @@
+use log::{info, warn};
+use tokio::process::Command;
+
+use crate::Container;
+
+/// Runs `command` through `sh -c` with the match in its environment, logging its exit status and stderr.
+pub async fn run_on_match(command: String, container: String, pattern: String, line: String) {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .env("WATCH_CONTAINER", &container)
+        .env("MATCHED_PATTERN", &pattern)
+        .env("MATCHED_LINE", &line)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim();
+
+            match (output.status.success(), stderr.is_empty()) {
+                (true, true) => info!(
+                    "[{container}] Command '{command}' finished with {}",
+                    output.status
+                ),
+                (true, false) => {
+                    info!(
+                        "[{container}] Command '{command}' finished with {}: {stderr}",
+                        output.status
+                    )
+                }
+                (false, _) => warn!(
+                    "[{container}] Command '{command}' finished with {}: {stderr}",
+                    output.status
+                ),
+            }
+        }
+        Err(err) => warn!("[{container}] Failed to run command '{command}': {err}"),
+    }
+}
+
+/// Runs a `pre_restart_command` or `post_restart_command` for one restart target through
+/// `sh -c`, logging its output. Returns whether it exited successfully.
+pub async fn run_hook(command: &str, kind: &str, container: &str, target: &Container) -> bool {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("WATCH_CONTAINER", container)
+        .env("RESTART_TARGET", &target.name)
+        .env("RESTART_TARGET_ID", &target.id)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let printed: Vec<&str> = [stdout.trim(), stderr.trim()]
+                .into_iter()
+                .filter(|text| !text.is_empty())
+                .collect();
+            let printed = match printed.is_empty() {
+                true => String::new(),
+                false => format!(": {}", printed.join("\n")),
+            };
+
+            match output.status.success() {
+                true => info!(
+                    "[{container}] {kind} command for {} finished with {}{printed}",
+                    target.name, output.status
+                ),
+                false => warn!(
+                    "[{container}] {kind} command for {} finished with {}{printed}",
+                    target.name, output.status
+                ),
+            }
+            output.status.success()
+        }
+        Err(err) => {
+            warn!(
+                "[{container}] Failed to run {} command '{command}' for {}: {err}",
+                kind.to_lowercase(),
+                target.name
+            );
+            false
+        }
+    }
+}
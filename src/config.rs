@@ -0,0 +1,448 @@
+use bollard::container::LogOutput;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerRestartConfig {
+    pub watch: String,
+    #[serde(default)]
+    pub restart: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub pattern: Vec<String>,
+    #[serde(default = "default_threshold")]
+    pub threshold: u32,
+    /// Only matches within this many seconds count toward `threshold`
+    #[serde(default)]
+    pub window: Option<u64>,
+    #[serde(default)]
+    pub stream: Stream,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub exclude_pattern: Vec<String>,
+    /// Restart every container in the watched container's compose project instead of `restart`
+    #[serde(default)]
+    pub restart_compose_project: bool,
+    /// Shell command to run when the threshold is reached
+    #[serde(default)]
+    pub on_match_command: Option<String>,
+    #[serde(default)]
+    pub command_mode: CommandMode,
+    /// Signal to send to the restart targets instead of restarting them
+    #[serde(default)]
+    pub signal: Option<String>,
+    /// Stop the restart targets instead of restarting them
+    #[serde(default)]
+    pub invert_restart: bool,
+    /// Overrides `--restart-timeout` for this watch
+    #[serde(default)]
+    pub restart_timeout: Option<u32>,
+    /// Overrides `--restart-delay` for this watch
+    #[serde(default)]
+    pub restart_delay: Option<u64>,
+    /// Overrides `--cooldown` for this watch
+    #[serde(default)]
+    pub cooldown: Option<u64>,
+    /// Overrides `--max-restarts` for this watch
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    /// Restart the targets when this pattern hasn't been seen for `expect_within` seconds
+    #[serde(default)]
+    pub expect_pattern: Option<String>,
+    #[serde(default)]
+    pub expect_within: Option<u64>,
+    /// Docker events of the watched container that trigger a restart, e.g. `die`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_event: Vec<String>,
+    /// Shell command to run before each restart target is restarted
+    #[serde(default)]
+    pub pre_restart_command: Option<String>,
+    /// Shell command to run after each restart target was restarted
+    #[serde(default)]
+    pub post_restart_command: Option<String>,
+    /// Skip a target's restart when `pre_restart_command` fails
+    #[serde(default)]
+    pub pre_restart_gate: bool,
+    /// Daemon the watched container and its restart targets run on, instead of `--docker-host`
+    #[serde(default)]
+    pub docker_host: Option<String>,
+}
+
+impl ContainerRestartConfig {
+    /// Restarts `restart` when `pattern` shows up in the logs of `watch`, with every
+    /// other option at its default.
+    pub fn new(watch: impl Into<String>, restart: Vec<String>, pattern: impl Into<String>) -> Self {
+        ContainerRestartConfig {
+            watch: watch.into(),
+            restart,
+            pattern: vec![pattern.into()],
+            threshold: default_threshold(),
+            window: None,
+            stream: Stream::default(),
+            exclude_pattern: Vec::new(),
+            restart_compose_project: false,
+            on_match_command: None,
+            command_mode: CommandMode::default(),
+            signal: None,
+            invert_restart: false,
+            restart_timeout: None,
+            restart_delay: None,
+            cooldown: None,
+            max_restarts: None,
+            expect_pattern: None,
+            expect_within: None,
+            on_event: Vec::new(),
+            pre_restart_command: None,
+            post_restart_command: None,
+            pre_restart_gate: false,
+            docker_host: None,
+        }
+    }
+}
+
+/// Whether `on_match_command` runs alongside the restart or replaces it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandMode {
+    #[default]
+    Also,
+    Instead,
+}
+
+/// Which of the container's output streams a pattern is matched against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    #[default]
+    Both,
+}
+
+impl Stream {
+    pub fn stdout(&self) -> bool {
+        matches!(self, Stream::Stdout | Stream::Both)
+    }
+
+    pub fn stderr(&self) -> bool {
+        matches!(self, Stream::Stderr | Stream::Both)
+    }
+
+    /// TTY containers multiplex everything onto `Console`, which is treated as stdout.
+    pub fn includes(&self, log: &LogOutput) -> bool {
+        match log {
+            LogOutput::StdOut { .. } | LogOutput::Console { .. } => self.stdout(),
+            LogOutput::StdErr { .. } => self.stderr(),
+            LogOutput::StdIn { .. } => false,
+        }
+    }
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+/// Accepts either a single string or a list of strings.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    struct OneOrMany;
+
+    impl<'de> Visitor<'de> for OneOrMany {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a string or a list of strings")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(vec![value.to_string()])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany)
+}
+
+/// The config file is either a bare list of watches or a mapping with named
+/// restart `groups` alongside the `watches`.
+#[derive(Debug)]
+struct ConfigFile {
+    groups: HashMap<String, Vec<String>>,
+    watches: Vec<ContainerRestartConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigSections {
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+    watches: Vec<ContainerRestartConfig>,
+}
+
+impl<'de> Deserialize<'de> for ConfigFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ConfigFileVisitor;
+
+        impl<'de> Visitor<'de> for ConfigFileVisitor {
+            type Value = ConfigFile;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of watches or a mapping with 'groups' and 'watches'")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Ok(ConfigFile {
+                    groups: HashMap::new(),
+                    watches: Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))?,
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let sections: ConfigSections =
+                    Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(ConfigFile {
+                    groups: sections.groups,
+                    watches: sections.watches,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(ConfigFileVisitor)
+    }
+}
+
+/// Loads the watch configs from a YAML file containing a list of entries, e.g.
+///
+/// ```yaml
+/// - watch: container-1
+///   restart: [container-2]
+///   pattern: [hello_world, goodbye_world]
+///   threshold: 2
+/// ```
+///
+/// or a mapping of `groups` and `watches`, where `restart` entries of the form
+/// `@name` expand to the members of that group. `.toml` and `.json` files are parsed
+/// as such, TOML only supporting the mapping form as it has no top-level lists.
+pub fn load_config_file(
+    path: &Path,
+) -> Result<Vec<ContainerRestartConfig>, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read config file '{}': {err}", path.display()))?;
+
+    // .yaml, .yml and anything else are read as YAML
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let file: ConfigFile = match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("toml") => toml::from_str(&contents).map_err(|err| err.to_string()),
+        Some("json") => serde_json::from_str(&contents).map_err(|err| err.to_string()),
+        _ => serde_yaml::from_str(&contents).map_err(|err| err.to_string()),
+    }
+    .map_err(|err| format!("Failed to parse config file '{}': {err}", path.display()))?;
+
+    let mut configs = file.watches;
+    for config in &mut configs {
+        config.restart = expand_groups(&config.restart, &file.groups).map_err(|group| {
+            format!(
+                "Failed to parse config file '{}': restart for '{}' references unknown group '@{group}'",
+                path.display(),
+                config.watch
+            )
+        })?;
+    }
+
+    for config in &mut configs {
+        expand_env_vars(config)
+            .map_err(|err| format!("Failed to parse config file '{}': {err}", path.display()))?;
+    }
+
+    Ok(configs)
+}
+
+/// Reads one pattern per line from `path`, skipping blank lines and `#` comments.
+pub fn load_pattern_file(path: &Path) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read pattern file '{}': {err}", path.display()))?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Replaces `@group` entries with the group's members, or returns the unknown group name.
+fn expand_groups(
+    restart: &[String],
+    groups: &HashMap<String, Vec<String>>,
+) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+
+    for target in restart {
+        match target.strip_prefix('@') {
+            Some(group) => match groups.get(group) {
+                Some(members) => expanded.extend(members.iter().cloned()),
+                None => return Err(group.to_string()),
+            },
+            None => expanded.push(target.clone()),
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Expands environment variables in the config's `watch` and `restart` values and all of
+/// its patterns.
+pub fn expand_env_vars(config: &mut ContainerRestartConfig) -> Result<(), String> {
+    let watch = config.watch.clone();
+    let expand = |value: &mut String| {
+        *value = expand_env(value).map_err(|err| format!("{err} (in the watch for '{watch}')"))?;
+        Ok::<_, String>(())
+    };
+
+    expand(&mut config.watch)?;
+    config.restart.iter_mut().try_for_each(expand)?;
+    config.pattern.iter_mut().try_for_each(expand)?;
+    config.exclude_pattern.iter_mut().try_for_each(expand)?;
+    config.expect_pattern.iter_mut().try_for_each(expand)
+}
+
+/// Expands `$VAR`, `${VAR}` and `${VAR:-default}`, the default also applying when the
+/// variable is empty. `$$` is a literal `$`, and a `$` not followed by a name is kept as is
+/// so regex anchors like `error$` keep working.
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| format!("Unterminated '${{' in '{value}'"))?;
+            let (name, default) = match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            };
+            expanded.push_str(&env_var(name, default)?);
+            rest = &braced[end + 1..];
+        } else {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            if len == 0 || after.starts_with(|c: char| c.is_ascii_digit()) {
+                expanded.push('$');
+                rest = after;
+                continue;
+            }
+            expanded.push_str(&env_var(&after[..len], None)?);
+            rest = &after[len..];
+        }
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn env_var(name: &str, default: Option<&str>) -> Result<String, String> {
+    match (env::var(name), default) {
+        (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Err(format!("Environment variable '{name}' is not set")),
+    }
+}
+
+/// Checks invariants that hold regardless of where the configs came from.
+pub fn validate_configs(
+    configs: &[ContainerRestartConfig],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut seen = HashSet::new();
+
+    for config in configs {
+        let instead = config.command_mode == CommandMode::Instead;
+
+        if instead && config.on_match_command.is_none() {
+            return Err(format!(
+                "command_mode for '{}' is 'instead' but no on_match_command is set",
+                config.watch
+            )
+            .into());
+        }
+
+        if config.restart.is_empty() && !config.restart_compose_project && !instead {
+            return Err(format!(
+                "restart for '{}' must not be empty unless restart_compose_project is set or command_mode is 'instead'",
+                config.watch
+            )
+            .into());
+        }
+
+        if config.pattern.is_empty()
+            && config.expect_pattern.is_none()
+            && config.on_event.is_empty()
+        {
+            return Err(format!(
+                "pattern for '{}' must not be empty unless expect_pattern or on_event is set",
+                config.watch
+            )
+            .into());
+        }
+
+        if config.expect_pattern.is_some() != config.expect_within.is_some() {
+            return Err(format!(
+                "expect_pattern and expect_within for '{}' must be set together",
+                config.watch
+            )
+            .into());
+        }
+
+        if config.threshold == 0 {
+            return Err(format!("threshold for '{}' must be at least 1", config.watch).into());
+        }
+
+        if config.max_restarts == Some(0) {
+            return Err(format!("max_restarts for '{}' must be at least 1", config.watch).into());
+        }
+
+        if config.expect_within == Some(0) {
+            return Err(format!("expect_within for '{}' must be at least 1", config.watch).into());
+        }
+
+        if config.invert_restart && config.signal.is_some() {
+            return Err(format!(
+                "signal and invert_restart for '{}' cannot both be set",
+                config.watch
+            )
+            .into());
+        }
+
+        if config.pre_restart_gate && config.pre_restart_command.is_none() {
+            return Err(format!(
+                "pre_restart_gate for '{}' is set but no pre_restart_command is",
+                config.watch
+            )
+            .into());
+        }
+
+        if !seen.insert((config.docker_host.as_deref(), config.watch.as_str())) {
+            return Err(format!(
+                "'{}' is watched more than once. Combine its patterns into a single watch instead.",
+                config.watch
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
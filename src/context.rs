@@ -0,0 +1,106 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The Docker endpoint of a context created with `docker context create`.
+#[derive(Debug)]
+pub struct Context {
+    pub host: String,
+    pub tls: Option<TlsFiles>,
+}
+
+/// Client certificate, key and CA stored alongside a context.
+#[derive(Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub ca: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Endpoints", default)]
+    endpoints: HashMap<String, Endpoint>,
+}
+
+#[derive(Deserialize)]
+struct Endpoint {
+    #[serde(rename = "Host")]
+    host: Option<String>,
+}
+
+/// Looks up `name` under `$DOCKER_CONFIG/contexts` (`~/.docker/contexts` by default),
+/// returning `None` if there is no such context.
+///
+/// The CLI stores each context in a directory named after the SHA-256 of its name, so
+/// rather than hashing, every `meta.json` is read and matched on its `Name`.
+pub fn resolve(name: &str) -> Result<Option<Context>, Box<dyn std::error::Error>> {
+    resolve_in(
+        &config_dir().ok_or("neither DOCKER_CONFIG nor HOME is set")?,
+        name,
+    )
+}
+
+/// Looks up `name` under `config_dir/contexts`, as [`resolve`] does for the CLI's config
+/// directory.
+pub(crate) fn resolve_in(
+    config_dir: &Path,
+    name: &str,
+) -> Result<Option<Context>, Box<dyn std::error::Error>> {
+    let contexts = config_dir.join("contexts");
+
+    let entries = match fs::read_dir(contexts.join("meta")) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let dir = entry?.path();
+        let Ok(contents) = fs::read_to_string(dir.join("meta.json")) else {
+            continue;
+        };
+        let meta: Meta = serde_json::from_str(&contents)
+            .map_err(|err| format!("Failed to parse '{}': {err}", dir.display()))?;
+        if meta.name != name {
+            continue;
+        }
+
+        let host = meta
+            .endpoints
+            .get("docker")
+            .and_then(|endpoint| endpoint.host.clone())
+            .ok_or_else(|| format!("context '{name}' has no Docker endpoint"))?;
+
+        let tls_dir = contexts
+            .join("tls")
+            .join(dir.file_name().unwrap_or_default())
+            .join("docker");
+        let tls = TlsFiles {
+            cert: tls_dir.join("cert.pem"),
+            key: tls_dir.join("key.pem"),
+            ca: tls_dir.join("ca.pem"),
+        };
+        let has_tls = [&tls.cert, &tls.key, &tls.ca]
+            .iter()
+            .all(|path| path.is_file());
+
+        return Ok(Some(Context {
+            host,
+            tls: has_tls.then_some(tls),
+        }));
+    }
+
+    Ok(None)
+}
+
+fn config_dir() -> Option<PathBuf> {
+    env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))
+}
@@ -0,0 +1,117 @@
+use bollard::container::{
+    InspectContainerOptions, KillContainerOptions, ListContainersOptions, LogOutput, LogsOptions,
+    RestartContainerOptions, StartContainerOptions, StopContainerOptions,
+};
+use bollard::errors::Error;
+use bollard::secret::{ContainerInspectResponse, ContainerSummary, EventMessage};
+use bollard::system::EventsOptions;
+use bollard::Docker;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
+
+/// The Docker calls made while monitoring, so the monitoring logic can be driven by a
+/// fake daemon in tests.
+pub trait DockerOps: Send + Sync {
+    /// Containers in any of `statuses`, e.g. `running`.
+    fn list(
+        &self,
+        statuses: &[&str],
+    ) -> impl Future<Output = Result<Vec<ContainerSummary>, Error>> + Send;
+
+    fn logs(
+        &self,
+        id: &str,
+        options: LogsOptions<String>,
+    ) -> BoxStream<'static, Result<LogOutput, Error>>;
+
+    fn inspect(
+        &self,
+        id: &str,
+    ) -> impl Future<Output = Result<ContainerInspectResponse, Error>> + Send;
+
+    fn restart(
+        &self,
+        id: &str,
+        options: Option<RestartContainerOptions>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn stop(
+        &self,
+        id: &str,
+        options: Option<StopContainerOptions>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn kill(&self, id: &str, signal: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn start(&self, id: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Container events from now on, until the daemon goes away.
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>>;
+}
+
+impl DockerOps for Docker {
+    async fn list(&self, statuses: &[&str]) -> Result<Vec<ContainerSummary>, Error> {
+        let mut filters = HashMap::new();
+        filters.insert(
+            "status".to_string(),
+            statuses.iter().map(|status| status.to_string()).collect(),
+        );
+
+        self.list_containers(Some(ListContainersOptions {
+            all: true,
+            filters,
+            ..Default::default()
+        }))
+        .await
+    }
+
+    fn logs(
+        &self,
+        id: &str,
+        options: LogsOptions<String>,
+    ) -> BoxStream<'static, Result<LogOutput, Error>> {
+        Docker::logs(self, id, Some(options)).boxed()
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerInspectResponse, Error> {
+        self.inspect_container(id, None::<InspectContainerOptions>)
+            .await
+    }
+
+    async fn restart(
+        &self,
+        id: &str,
+        options: Option<RestartContainerOptions>,
+    ) -> Result<(), Error> {
+        self.restart_container(id, options).await
+    }
+
+    async fn stop(&self, id: &str, options: Option<StopContainerOptions>) -> Result<(), Error> {
+        self.stop_container(id, options).await
+    }
+
+    async fn kill(&self, id: &str, signal: &str) -> Result<(), Error> {
+        self.kill_container(id, Some(KillContainerOptions { signal }))
+            .await
+    }
+
+    async fn start(&self, id: &str) -> Result<(), Error> {
+        self.start_container(id, None::<StartContainerOptions<String>>)
+            .await
+    }
+
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>> {
+        let filters = HashMap::from([("type".to_string(), vec!["container".to_string()])]);
+
+        Docker::events(
+            self,
+            Some(EventsOptions {
+                filters,
+                ..Default::default()
+            }),
+        )
+        .boxed()
+    }
+}
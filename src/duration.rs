@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+/// Parses a duration like `30s`, `5m`, `1h30m` or `500ms` (units `ms`, `s`, `m`, `h` and
+/// `d`). A bare number is taken as seconds, so plain `--cooldown 60` keeps working.
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    parse(raw, Duration::from_secs)
+}
+
+/// Like [`parse_duration`], but rejects zero.
+pub fn parse_positive_duration(raw: &str) -> Result<Duration, String> {
+    match parse_duration(raw)? {
+        Duration::ZERO => Err("must be greater than zero".to_string()),
+        duration => Ok(duration),
+    }
+}
+
+/// Like [`parse_duration`], but a bare number is taken as milliseconds.
+pub fn parse_millis(raw: &str) -> Result<Duration, String> {
+    parse(raw, Duration::from_millis)
+}
+
+/// Like [`parse_duration`], for options that are stored or sent to Docker in whole seconds.
+pub fn parse_secs<T: TryFrom<u64>>(raw: &str) -> Result<T, String> {
+    let duration = parse_duration(raw)?;
+    if duration.subsec_nanos() != 0 {
+        return Err(format!("'{raw}' must be a whole number of seconds"));
+    }
+
+    T::try_from(duration.as_secs()).map_err(|_| format!("'{raw}' is too long"))
+}
+
+fn parse(raw: &str, bare: fn(u64) -> Duration) -> Result<Duration, String> {
+    let raw = raw.trim();
+    if let Ok(number) = raw.parse::<u64>() {
+        return Ok(bare(number));
+    }
+
+    let invalid = || format!("invalid duration '{raw}', expected e.g. 30s, 5m or 1h30m");
+    if raw.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = raw;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(digits);
+        let letters = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(letters);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+
+        let part = match unit {
+            "ms" => Some(Duration::from_millis(number)),
+            "s" => Some(Duration::from_secs(number)),
+            "m" => number.checked_mul(60).map(Duration::from_secs),
+            "h" => number.checked_mul(60 * 60).map(Duration::from_secs),
+            "d" => number.checked_mul(24 * 60 * 60).map(Duration::from_secs),
+            "" => return Err(invalid()),
+            unit => {
+                return Err(format!(
+                    "unknown unit '{unit}' in '{raw}', expected ms, s, m, h or d"
+                ))
+            }
+        };
+        total = part
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(|| format!("'{raw}' is too long"))?;
+        rest = tail;
+    }
+
+    Ok(total)
+}
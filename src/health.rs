@@ -0,0 +1,179 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+use crate::{now, AtomicInstant};
+
+/// Tracks the health of every Docker daemon being monitored, keyed by `docker_host`.
+#[derive(Debug)]
+pub struct Health {
+    daemons: Mutex<HashMap<Option<String>, Arc<Daemon>>>,
+    stale_after: Duration,
+    polled: Notify,
+}
+
+/// When one daemon's container list was last fetched successfully, and when any
+/// container watched on it last logged a line.
+#[derive(Debug)]
+pub struct Daemon {
+    last_poll: AtomicI64,
+    last_line: AtomicInstant,
+}
+
+impl Daemon {
+    pub fn record_line(&self) {
+        self.last_line.store_now();
+    }
+
+    /// How long it has been since any container watched on this daemon logged a line,
+    /// counting from when monitoring it started before the first one.
+    pub fn logs_silent_for(&self) -> Duration {
+        self.last_line.elapsed()
+    }
+}
+
+impl Health {
+    pub fn new(stale_after: Duration) -> Self {
+        Health {
+            daemons: Mutex::new(HashMap::new()),
+            stale_after,
+            polled: Notify::new(),
+        }
+    }
+
+    /// The daemon at `host`, which health checks wait on from the first call.
+    pub fn daemon(&self, host: &Option<String>) -> Arc<Daemon> {
+        let mut daemons = self.daemons.lock().unwrap();
+        Arc::clone(daemons.entry(host.clone()).or_insert_with(|| {
+            Arc::new(Daemon {
+                last_poll: AtomicI64::new(0),
+                last_line: AtomicInstant::default(),
+            })
+        }))
+    }
+
+    pub fn record_poll(&self, daemon: &Daemon) {
+        daemon.last_poll.store(now(), Ordering::Relaxed);
+        self.polled.notify_one();
+    }
+
+    /// Healthy once a poll of every daemon has succeeded within `stale_after`.
+    pub fn is_healthy(&self) -> bool {
+        let daemons = self.daemons.lock().unwrap();
+        !daemons.is_empty()
+            && daemons.values().all(|daemon| {
+                now() - daemon.last_poll.load(Ordering::Relaxed)
+                    <= self.stale_after.as_secs() as i64
+            })
+    }
+
+    /// The `GET /healthz` response.
+    pub fn reply(&self) -> Reply {
+        match self.is_healthy() {
+            true => Reply::text("200 OK", "ok"),
+            false => Reply::text("503 Service Unavailable", "unhealthy"),
+        }
+    }
+}
+
+/// A response from one of the endpoints `serve` routes to.
+#[derive(Debug)]
+pub struct Reply {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    pub fn text(status: &'static str, body: &str) -> Self {
+        Reply {
+            status,
+            content_type: "text/plain",
+            body: body.to_string(),
+        }
+    }
+
+    pub fn json(body: String) -> Self {
+        Reply {
+            status: "200 OK",
+            content_type: "application/json",
+            body,
+        }
+    }
+}
+
+/// GETs `url` after every successful poll that leaves all daemons healthy, so an external
+/// dead man's switch alerts when the pings stop, including when only one daemon is down.
+/// Polls that happen while a ping is in flight are covered by the next one.
+pub async fn heartbeat(health: &Health, client: &reqwest::Client, url: &str) {
+    loop {
+        health.polled.notified().await;
+        if !health.is_healthy() {
+            continue;
+        }
+
+        let result = client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => debug!("Pinged heartbeat URL {url}"),
+            Err(err) => warn!("Failed to ping heartbeat URL {url}: {err}"),
+        }
+    }
+}
+
+/// Answers each request with what `route` returns for its path (without the query
+/// string), and 404 when it returns `None`.
+pub async fn serve<F>(listener: TcpListener, route: F)
+where
+    F: Fn(&str) -> Option<Reply> + Clone + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let route = route.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = respond(stream, route).await {
+                        debug!("Health check connection failed: {err}");
+                    }
+                });
+            }
+            Err(err) => warn!("Failed to accept health check connection: {err}"),
+        }
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    route: impl Fn(&str) -> Option<Reply>,
+) -> std::io::Result<()> {
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..read]);
+
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|target| target.split('?').next());
+    let reply = path
+        .and_then(route)
+        .unwrap_or_else(|| Reply::text("404 Not Found", "not found"));
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status,
+        reply.content_type,
+        reply.body.len(),
+        reply.body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,38 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::notify::RestartEvent;
+
+/// The most recent restarts, oldest first, served on `GET /history`.
+#[derive(Debug)]
+pub struct History {
+    events: Mutex<VecDeque<RestartEvent>>,
+    size: usize,
+}
+
+impl History {
+    /// Keeps at most `size` restarts, none with 0.
+    pub fn new(size: usize) -> Self {
+        History {
+            events: Mutex::new(VecDeque::new()),
+            size,
+        }
+    }
+
+    pub fn record(&self, event: RestartEvent) {
+        if self.size == 0 {
+            return;
+        }
+
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.size {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    pub fn to_json(&self) -> String {
+        let events = self.events.lock().unwrap();
+        serde_json::to_string(&*events).unwrap_or_else(|_| "[]".to_string())
+    }
+}
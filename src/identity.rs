@@ -0,0 +1,31 @@
+use regex::Regex;
+use std::env;
+use std::fs;
+
+/// Best-effort lookup of the ID of the container this process is running in.
+///
+/// cgroup v1 exposes the full ID in `/proc/self/cgroup`. Under cgroup v2 it only
+/// shows up in the bind mounts Docker creates under `/var/lib/docker/containers`.
+/// Failing both, Docker's default hostname is the 12 character short ID.
+pub fn own_container_id() -> Option<String> {
+    let full_id = Regex::new(r"[0-9a-f]{64}").ok()?;
+
+    for path in ["/proc/self/cgroup", "/proc/self/mountinfo"] {
+        let Ok(contents) = fs::read_to_string(path) else {
+            continue;
+        };
+
+        let found = contents
+            .lines()
+            .filter(|line| path.ends_with("cgroup") || line.contains("/containers/"))
+            .find_map(|line| full_id.find(line));
+
+        if let Some(id) = found {
+            return Some(id.as_str().to_string());
+        }
+    }
+
+    env::var("HOSTNAME")
+        .ok()
+        .filter(|hostname| hostname.len() == 12 && hostname.chars().all(|c| c.is_ascii_hexdigit()))
+}
@@ -0,0 +1,42 @@
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Randomizes an interval by up to ±`max` using a xorshift generator seeded once.
+#[derive(Debug)]
+pub struct Jitter {
+    max: Duration,
+    state: u64,
+}
+
+impl Jitter {
+    pub fn new(max: Duration) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+
+        // Mix in the PID so instances started in the same instant still drift apart,
+        // and keep the state non-zero, which xorshift can't leave
+        Jitter {
+            max,
+            state: (nanos ^ (u64::from(process::id()) << 32)) | 1,
+        }
+    }
+
+    pub fn apply(&mut self, interval: Duration) -> Duration {
+        if self.max.is_zero() {
+            return interval;
+        }
+
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        // Uniform in [-max, max]
+        let max_ms = self.max.as_millis() as u64;
+        let offset = (self.state % (2 * max_ms + 1)) as i64 - max_ms as i64;
+        match offset < 0 {
+            true => interval.saturating_sub(Duration::from_millis(offset.unsigned_abs())),
+            false => interval + Duration::from_millis(offset as u64),
+        }
+    }
+}
@@ -0,0 +1,1930 @@
+//! Monitors container logs and restarts containers when a pattern shows up.
+//!
+//! The `docker-restarter` binary is a thin wrapper around [`cli::run`]. To embed the
+//! monitor in another service, build a [`Monitor`] from [`ContainerRestartConfig`]s:
+//!
+//! ```no_run
+//! use bollard::Docker;
+//! use docker_restarter::{ContainerRestartConfig, Monitor};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = ContainerRestartConfig::new("api", vec!["api".into()], "out of memory");
+//! let docker = Docker::connect_with_socket_defaults()?;
+//!
+//! Monitor::new(vec![config], docker)?
+//!     .on_restart(|event| println!("Restarted {:?}", event.restarted))
+//!     .run(async { tokio::signal::ctrl_c().await.unwrap() })
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+
+mod audit;
+pub mod cli;
+mod command;
+mod config;
+mod context;
+mod docker;
+mod duration;
+mod health;
+mod history;
+mod identity;
+mod jitter;
+mod limits;
+mod logging;
+mod matcher;
+mod metrics;
+mod notify;
+mod selector;
+mod state;
+mod telemetry;
+mod timestamp;
+
+use audit::{AuditEntry, AuditLog};
+use bollard::container::{LogOutput, LogsOptions, RestartContainerOptions, StopContainerOptions};
+use bollard::secret::{ContainerSummary, EventMessage};
+use bollard::Docker;
+use cli::Args;
+use config::validate_configs;
+use docker::DockerOps;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use health::{Daemon, Health};
+use history::History;
+use jitter::Jitter;
+use limits::{RateLimiter, Throttle};
+use log::{debug, error, info, warn};
+use matcher::{strip_ansi, MatchOptions, Matcher};
+use metrics::Metrics;
+use notify::{
+    send_webhook, BreakerTrippedEvent, RemediationFailedEvent, RestartIneffectiveEvent,
+    SlackMessage,
+};
+use selector::{MatchMode, Selector};
+use serde::Serialize;
+use state::{StateStore, WatchState};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::{pending, Future};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Duration, Instant};
+use tracing::{info_span, Instrument};
+
+pub use config::{CommandMode, ContainerRestartConfig, Stream};
+pub use notify::{MatchContext, RestartEvent};
+
+/// Watches containers' logs and restarts their targets, with every other setting at the
+/// CLI's defaults.
+pub struct Monitor {
+    docker: Docker,
+    watches: Vec<Watch>,
+    settings: Settings,
+}
+
+impl Monitor {
+    /// Fails if a config is invalid, e.g. has no pattern or an invalid regex.
+    pub fn new(
+        configs: Vec<ContainerRestartConfig>,
+        docker: Docker,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        validate_configs(&configs)?;
+        if let Some(config) = configs.iter().find(|config| config.docker_host.is_some()) {
+            return Err(format!(
+                "'{}' sets docker_host, which only the command line supports",
+                config.watch
+            )
+            .into());
+        }
+        let watches = compile_watches(configs, MatchOptions::default(), MatchMode::Exact)?;
+        let settings = Settings::new(&Args::defaults(), None, None, None)?;
+
+        Ok(Monitor {
+            docker,
+            watches,
+            settings,
+        })
+    }
+
+    /// Calls `hook` after every successful restart.
+    pub fn on_restart(mut self, hook: impl Fn(&RestartEvent) + Send + Sync + 'static) -> Self {
+        self.settings.on_restart = Some(RestartHook(Box::new(hook)));
+        self
+    }
+
+    /// Monitors until `shutdown` resolves.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) {
+        // Nothing reloads the watches, but the sender has to outlive the monitor
+        let (_reload_tx, reloads) = tokio::sync::watch::channel(Arc::new(self.watches));
+        let dockers = HashMap::from([(None, self.docker)]);
+        run_until(&dockers, reloads, &Arc::new(self.settings), shutdown).await;
+    }
+}
+
+/// See [`Monitor::on_restart`].
+struct RestartHook(Box<dyn Fn(&RestartEvent) + Send + Sync>);
+
+impl fmt::Debug for RestartHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RestartHook")
+    }
+}
+
+/// Docker connections keyed by the `docker_host` of the watches on them, `None` being the
+/// default connection.
+type Dockers = HashMap<Option<String>, Docker>;
+
+/// The watches whose containers are on `host`.
+fn watches_on(watches: &[Watch], host: &Option<String>) -> Vec<Watch> {
+    watches
+        .iter()
+        .filter(|watch| watch.config.docker_host == *host)
+        .cloned()
+        .collect()
+}
+
+/// How the daemon at `host` is referred to in logs.
+fn daemon_name(host: &Option<String>) -> String {
+    match host {
+        Some(host) => format!("Docker at {host}"),
+        None => "Docker".to_string(),
+    }
+}
+
+/// Monitors every daemon until `shutdown` resolves, then stops every monitoring task.
+/// Each daemon is polled on its own, so one that is unreachable doesn't hold up the others.
+async fn run_until(
+    dockers: &Dockers,
+    reloads: tokio::sync::watch::Receiver<Arc<Vec<Watch>>>,
+    settings: &Arc<Settings>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let mut tasks: Vec<HashMap<String, JoinHandle<()>>> =
+        dockers.keys().map(|_| HashMap::new()).collect();
+    // Registered up front so health checks wait on daemons that never answer
+    for host in dockers.keys() {
+        settings.health.daemon(host);
+    }
+    let monitors = dockers
+        .iter()
+        .zip(&mut tasks)
+        .map(|((host, docker), tasks)| {
+            let mut reloads = reloads.clone();
+            async move { monitor_containers(docker, host, &mut reloads, settings, tasks).await }
+        });
+
+    tokio::select! {
+        _ = futures_util::future::join_all(monitors) => {}
+        _ = report_match_stats(settings) => {}
+        _ = shutdown => {}
+    }
+
+    info!(
+        "Shutting down, stopping {} monitoring task(s)...",
+        tasks.iter().map(HashMap::len).sum::<usize>()
+    );
+
+    for (_, task) in tasks.iter_mut().flat_map(HashMap::drain) {
+        task.abort();
+    }
+    if settings.match_stats_interval.is_some() {
+        settings.metrics.report_match_stats();
+    }
+    flush_state(settings);
+}
+
+/// Logs the match stats of every daemon's containers each `--match-stats` interval.
+async fn report_match_stats(settings: &Settings) {
+    let Some(interval) = settings.match_stats_interval else {
+        return pending().await;
+    };
+    loop {
+        sleep(interval).await;
+        settings.metrics.report_match_stats();
+    }
+}
+
+/// Writes out the lines seen since the state file was last written, if there is one.
+fn flush_state(settings: &Settings) {
+    if let Some(state) = &settings.state {
+        if let Err(e) = state.flush() {
+            warn!("Failed to write state file: {e}");
+        }
+    }
+}
+
+async fn monitor_containers(
+    docker: &Docker,
+    host: &Option<String>,
+    reloads: &mut tokio::sync::watch::Receiver<Arc<Vec<Watch>>>,
+    settings: &Arc<Settings>,
+    tasks: &mut HashMap<String, JoinHandle<()>>,
+) {
+    let mut containers: HashMap<String, MappedContainer> = HashMap::new();
+    let mut trackers: HashMap<String, Arc<Mutex<Tracker>>> = HashMap::new();
+    let mut last_report = Instant::now();
+    let mut last_uptime_report = Instant::now();
+    let mut retry_delay = settings.reconnect_delay;
+    let mut disconnected = false;
+    let mut jitter = Jitter::new(settings.poll_jitter);
+    let mut events = None;
+    let mut watches = Arc::new(watches_on(&reloads.borrow_and_update(), host));
+    let daemon = daemon_name(host);
+    let health = settings.health.daemon(host);
+
+    loop {
+        if reloads.has_changed().unwrap_or(false) {
+            let reloaded = Arc::new(watches_on(&reloads.borrow_and_update(), host));
+
+            // Only containers whose watch entry was removed or edited lose their task and state
+            containers.retain(|id, container| {
+                let unchanged = watches
+                    .iter()
+                    .find(|watch| watch.selector == container.watch)
+                    .is_some_and(|old| reloaded.iter().any(|new| new.config == old.config));
+
+                if !unchanged {
+                    info!(
+                        "[{}] Watch changed, restarting its monitoring",
+                        container.name
+                    );
+                    if let Some(task) = tasks.remove(id) {
+                        task.abort();
+                    }
+                    trackers.remove(&container.key);
+                }
+                unchanged
+            });
+
+            watches = reloaded;
+        }
+
+        if events.is_none() && watches.iter().any(|watch| !watch.events.is_empty()) {
+            events = Some(DockerOps::events(docker));
+        }
+
+        let selectors: Vec<Selector> = watches.iter().map(|w| w.selector.clone()).collect();
+        let poll = async {
+            if settings.resubscribe_on_reconnect {
+                docker.ping().await?;
+            }
+            get_filtered_containers(docker, settings, &selectors).await
+        };
+        let containers_result = poll.await.map(|containers| {
+            containers
+                .iter()
+                .filter_map(|container| {
+                    let Some(watch) = watches.get(container.selector) else {
+                        warn!(
+                            "[{}] No watch config matches this container, skipping",
+                            container.name
+                        );
+                        return None;
+                    };
+
+                    Some((
+                        container.id.clone(),
+                        MappedContainer::new(container, watch, settings),
+                    ))
+                })
+                .collect::<HashMap<String, MappedContainer>>()
+        });
+
+        let new_containers = match containers_result {
+            Ok(cons) => cons,
+            Err(err) => {
+                disconnected = true;
+                settings.metrics.record_docker_error();
+                error!("Failed to get containers from {daemon}: {err}");
+                info!("Will sleep {}s and try again...", retry_delay.as_secs());
+
+                sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(settings.max_reconnect_delay);
+                continue;
+            }
+        };
+
+        retry_delay = settings.reconnect_delay;
+        settings.health.record_poll(&health);
+
+        // Streams opened before the daemon went away can stay open without delivering lines
+        if std::mem::take(&mut disconnected) && settings.resubscribe_on_reconnect {
+            info!("Reconnected to {daemon}, reopening all log streams");
+            for (_, task) in tasks.drain() {
+                task.abort();
+            }
+            containers.clear();
+        }
+
+        if stalled(settings, &health, &daemon, new_containers.len()) {
+            for (_, task) in tasks.drain() {
+                task.abort();
+            }
+            containers.clear();
+        }
+
+        for (id, container) in &containers {
+            // Also restart monitoring when a reload moved the container to another watch
+            let same_watch = new_containers
+                .get(id)
+                .is_some_and(|new| new.watch == container.watch);
+            if !same_watch {
+                if let Some(task) = tasks.remove(id) {
+                    task.abort();
+                }
+            }
+        }
+
+        containers = new_containers;
+
+        // Tasks that ended on their own (a panic, or the container briefly not running)
+        // are started again below for containers that are still around
+        tasks.retain(|id, task| match containers.get(id) {
+            Some(container) if task.is_finished() => {
+                warn!(
+                    "[{}] Monitoring stopped unexpectedly, starting it again",
+                    container.name
+                );
+                false
+            }
+            _ => true,
+        });
+
+        for container in containers.values() {
+            if !tasks.contains_key(&container.id) {
+                let mut task_container = container.clone();
+                let tracker = trackers.entry(container.key.clone()).or_insert_with(|| {
+                    let mut tracker = Tracker::default();
+                    if let Some(state) = settings.state.as_ref().and_then(|s| s.get(&container.key))
+                    {
+                        tracker.restore(&state);
+                    }
+                    Arc::new(Mutex::new(tracker))
+                });
+                task_container.tracker = Arc::clone(tracker);
+
+                let container_id = container.id.clone();
+                let container_name = container.name.clone();
+                let docker_clone = docker.clone();
+                let task_settings = Arc::clone(settings);
+
+                match &container.watch {
+                    Selector::Name(_) => info!("[{container_name}] Monitoring logs..."),
+                    selector => info!("[{container_name}] Monitoring logs (matched {selector})..."),
+                }
+                if task_container.restart.is_empty() {
+                    warn!("[{container_name}] Container has no '{COMPOSE_PROJECT_LABEL}' label, nothing will be restarted");
+                }
+
+                let task_handle = tokio::spawn(async move {
+                    if let Err(e) =
+                        monitor_logs(&docker_clone, &task_settings, &task_container).await
+                    {
+                        error!("[{container_name}] Error monitoring logs for {container_id}: {e}");
+                    }
+                });
+
+                tasks.insert(container.id.clone(), task_handle);
+            }
+        }
+
+        for container in containers.values() {
+            settings
+                .metrics
+                .report(&container.key, last_report.elapsed());
+        }
+        last_report = Instant::now();
+        flush_state(settings);
+
+        if let Some(interval) = settings.uptime_report_interval {
+            if last_uptime_report.elapsed() >= interval {
+                for container in containers.values() {
+                    settings.metrics.report_uptime(&container.key);
+                }
+                last_uptime_report = Instant::now();
+            }
+        }
+
+        // Events are handled as they arrive, the next poll still happens on schedule
+        let next_poll = sleep(jitter.apply(settings.poll_interval));
+        tokio::pin!(next_poll);
+        loop {
+            tokio::select! {
+                _ = &mut next_poll => break,
+                Ok(()) = reloads.changed() => break,
+                event = next_event(&mut events) => match event {
+                    Some(Ok(event)) => handle_event(docker, settings, &containers, &trackers, event),
+                    Some(Err(err)) => {
+                        settings.metrics.record_docker_error();
+                        warn!("Docker event stream failed, reopening it at the next poll: {err}");
+                        events = None;
+                    }
+                    None => events = None,
+                },
+            }
+        }
+    }
+}
+
+/// Whether none of the `watched` containers on a daemon logged a line for `--stall-timeout`.
+/// A daemon in a bad state can stall every log stream at once without any of them
+/// erroring, which per-stream timeouts only catch if they are set. The silence is counted
+/// from now on once this returns true, giving the reopened streams a full timeout.
+fn stalled(settings: &Settings, health: &Daemon, daemon: &str, watched: usize) -> bool {
+    let Some(timeout) = settings.stall_timeout.filter(|_| watched > 0) else {
+        return false;
+    };
+    let silent = health.logs_silent_for();
+    if silent < timeout {
+        return false;
+    }
+
+    error!(
+        "No logs received from any of the {watched} container(s) watched on {daemon} for {}s, reopening their log streams",
+        silent.as_secs()
+    );
+    health.record_line();
+    true
+}
+
+async fn next_event(
+    events: &mut Option<BoxStream<'static, Result<EventMessage, bollard::errors::Error>>>,
+) -> Option<Result<EventMessage, bollard::errors::Error>> {
+    match events {
+        Some(events) => events.next().await,
+        None => pending().await,
+    }
+}
+
+/// Restarts the targets of the watch whose container emitted `event`, if it's one of the
+/// watch's `on_event` events.
+fn handle_event(
+    docker: &Docker,
+    settings: &Arc<Settings>,
+    containers: &HashMap<String, MappedContainer>,
+    trackers: &HashMap<String, Arc<Mutex<Tracker>>>,
+    event: EventMessage,
+) {
+    let arrived = Instant::now();
+    let (Some(action), Some(id)) = (event.action, event.actor.and_then(|actor| actor.id)) else {
+        return;
+    };
+    let Some(container) = containers.get(&id) else {
+        return;
+    };
+    let Some(matcher) = event_trigger(container, &action) else {
+        return;
+    };
+    let Some(tracker) = trackers.get(&container.key) else {
+        return;
+    };
+
+    let mut container = container.clone();
+    container.tracker = Arc::clone(tracker);
+    let matcher = matcher.clone();
+    let docker = docker.clone();
+    let settings = Arc::clone(settings);
+
+    tokio::spawn(async move {
+        let mut tracker = container.tracker.lock().await;
+
+        // Restarting the watched container itself emits events like `kill` and `die`
+        let restarting = tracker
+            .last_restart
+            .is_some_and(|restart| restart >= arrived);
+        if restarting || settings.recent_restarts.contains(&container.id) {
+            debug!(
+                "[{}] Ignoring '{action}' event caused by a restart",
+                container.name
+            );
+            return;
+        }
+
+        let trigger = Trigger {
+            pattern: &matcher,
+            line: &action,
+            arrived,
+            cause: Cause::Event,
+            context: MatchContext::default(),
+        };
+        remediate(&docker, &settings, &container, &mut tracker, trigger).await;
+    });
+}
+
+/// The `on_event` entry of `container` that `action` matches, if any.
+fn event_trigger<'a>(container: &'a MappedContainer, action: &str) -> Option<&'a Matcher> {
+    // `health_status` also matches `health_status: unhealthy`
+    let kind = action.split(':').next().unwrap_or_default();
+    container
+        .events
+        .iter()
+        .find(|matcher| matcher.is_match(action) || matcher.is_match(kind))
+}
+
+/// Delay before the first `--restart-retries` retry, doubled for each one after.
+const RESTART_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn compile_watches(
+    configs: Vec<ContainerRestartConfig>,
+    options: MatchOptions,
+    match_mode: MatchMode,
+) -> Result<Vec<Watch>, Box<dyn std::error::Error>> {
+    configs
+        .into_iter()
+        .map(|config| {
+            // --match-mode only loosens the watch, restart targets are always exact
+            let selector = Selector::parse_with_mode(&config.watch, match_mode)?;
+            let restart = config
+                .restart
+                .iter()
+                .map(|target| Selector::parse(target))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let compile = |patterns: &[String]| {
+                patterns
+                    .iter()
+                    .map(|pattern| {
+                        Matcher::new(pattern, options).map_err(|err| {
+                            format!("Invalid pattern '{pattern}' for '{}': {err}", config.watch)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let patterns = compile(&config.pattern)?;
+            let excludes = compile(&config.exclude_pattern)?;
+            let expect = match (&config.expect_pattern, config.expect_within) {
+                (Some(pattern), Some(within)) => {
+                    let matcher = Matcher::new(pattern, options).map_err(|err| {
+                        format!(
+                            "Invalid expect_pattern '{pattern}' for '{}': {err}",
+                            config.watch
+                        )
+                    })?;
+                    Some((matcher, Duration::from_secs(within)))
+                }
+                _ => None,
+            };
+            let events = config
+                .on_event
+                .iter()
+                .map(|event| Matcher::WholeLine(event.trim().to_string()))
+                .collect();
+
+            Ok(Watch {
+                config,
+                selector,
+                restart,
+                patterns,
+                excludes,
+                expect,
+                events,
+            })
+        })
+        .collect()
+}
+
+/// A watch config with its selectors parsed and patterns compiled.
+#[derive(Debug, Clone)]
+struct Watch {
+    config: ContainerRestartConfig,
+    selector: Selector,
+    restart: Vec<Selector>,
+    patterns: Vec<Matcher>,
+    excludes: Vec<Matcher>,
+    expect: Option<(Matcher, Duration)>,
+    events: Vec<Matcher>,
+}
+
+/// Run-wide options shared by every monitoring task.
+#[derive(Debug)]
+struct Settings {
+    poll_interval: Duration,
+    poll_jitter: Duration,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+    resubscribe_delay: Duration,
+    log_lookback: Duration,
+    log_idle_timeout: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    uptime_report_interval: Option<Duration>,
+    /// How often to log line, match and restart counts, from `--match-stats`
+    match_stats_interval: Option<Duration>,
+    resubscribe_on_reconnect: bool,
+    tail: Option<u64>,
+    /// Read each log stream to its end once instead of following it, from `--scan-once`
+    scan_once: bool,
+    strip_ansi: bool,
+    max_line_bytes: Option<usize>,
+    cooldown: Option<Duration>,
+    cooldown_per_pattern: bool,
+    debounce: Option<Duration>,
+    startup_grace: Option<Duration>,
+    max_restarts: Option<u32>,
+    restart_window: Option<Duration>,
+    /// Never restart a watch's targets again after its first restart, from
+    /// `--restart-on-first-match-only`
+    first_match_only: bool,
+    /// How long after a restart its pattern matching again means the restart didn't help
+    verify_window: Option<Duration>,
+    /// Shared by every Docker call that restarts a target, from `--max-concurrent-restarts`
+    restart_permits: Option<Semaphore>,
+    rate_limiter: Option<RateLimiter>,
+    webhook_url: Option<String>,
+    slack_webhook: Option<String>,
+    /// Container IDs restarted recently by any watch, so correlated triggers restart them once
+    recent_restarts: Throttle,
+    /// Suppresses identical Slack messages within the cooldown
+    slack_throttle: Throttle,
+    http: reqwest::Client,
+    restart_timeout: Option<u32>,
+    restart_delay: Option<Duration>,
+    restart_retries: u32,
+    start_stopped: bool,
+    /// The states `get_running_containers` lists, from `--container-status`
+    container_statuses: Vec<String>,
+    dry_run: bool,
+    /// The monitor's own container ID, unless `--allow-self` was given
+    self_id: Option<String>,
+    self_warned: AtomicBool,
+    state: Option<StateStore>,
+    audit: Option<AuditLog>,
+    /// Tells `main` to shut down after a restart, set by `--once`
+    once: Option<mpsc::Sender<()>>,
+    health: Health,
+    history: History,
+    metrics: Metrics,
+    on_restart: Option<RestartHook>,
+}
+
+impl Settings {
+    fn is_self(&self, id: &str) -> bool {
+        self.self_id
+            .as_deref()
+            .is_some_and(|self_id| id.starts_with(self_id))
+    }
+
+    /// Tells `main` to shut down with `--once`, after a restart or whatever stood in for one:
+    /// a dry run or an `instead` command.
+    fn signal_once(&self) {
+        if let Some(once) = &self.once {
+            let _ = once.try_send(());
+        }
+    }
+
+    /// Delivers `payload` to the webhook in the background, if one is configured. Nothing
+    /// is sent in a dry run, since nothing was restarted.
+    fn notify<T: Serialize + Send + Sync + 'static>(&self, watch: &str, payload: T) {
+        if let Some(url) = self.webhook_url.as_ref().filter(|_| !self.dry_run) {
+            let client = self.http.clone();
+            let url = url.clone();
+            let watch = watch.to_string();
+
+            tokio::spawn(async move {
+                send_webhook(&client, &url, &watch, &payload).await;
+            });
+        }
+    }
+
+    /// Posts `text` to Slack in the background, unless the same text was just sent or this
+    /// is a dry run.
+    fn notify_slack(&self, watch: &str, text: String) {
+        let Some(url) = self.slack_webhook.as_ref().filter(|_| !self.dry_run) else {
+            return;
+        };
+
+        if !self.slack_throttle.allow(&text) {
+            debug!("[{watch}] Skipping duplicate Slack message");
+            return;
+        }
+
+        let client = self.http.clone();
+        let url = url.clone();
+        let watch = watch.to_string();
+
+        tokio::spawn(async move {
+            send_webhook(&client, &url, &watch, &SlackMessage { text }).await;
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Container {
+    id: String,
+    name: String,
+    /// Index of the selector this container matched
+    selector: usize,
+    compose_project: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct MappedContainer {
+    id: String,
+    name: String,
+    /// The name qualified with the watch's `docker_host`, identifying the container across
+    /// daemons in metrics, trackers and the state file
+    key: String,
+    watch: Selector,
+    restart: Vec<Selector>,
+    patterns: Vec<Matcher>,
+    excludes: Vec<Matcher>,
+    /// Pattern that must show up at least once per interval, from `expect_pattern`
+    expect: Option<(Matcher, Duration)>,
+    /// Docker event actions that trigger a restart, from `on_event`
+    events: Vec<Matcher>,
+    threshold: u32,
+    window: Option<Duration>,
+    stream: Stream,
+    on_match_command: Option<String>,
+    command_mode: CommandMode,
+    // The config entry's overrides, falling back to the global flags
+    restart_timeout: Option<u32>,
+    restart_delay: Option<Duration>,
+    cooldown: Option<Duration>,
+    max_restarts: Option<u32>,
+    signal: Option<String>,
+    invert_restart: bool,
+    pre_restart_command: Option<String>,
+    post_restart_command: Option<String>,
+    pre_restart_gate: bool,
+    /// When this container's monitoring task was started, for `--startup-grace`
+    monitoring_since: Instant,
+    /// Shared with any earlier task for a container of the same name
+    tracker: Arc<Mutex<Tracker>>,
+    /// The daemon the container runs on, for the stall watchdog
+    daemon: Arc<Daemon>,
+}
+
+impl MappedContainer {
+    /// Resolves `watch` for one of the containers it matched, applying the watch's
+    /// overrides over the global flags.
+    fn new(container: &Container, watch: &Watch, settings: &Settings) -> Self {
+        let config = &watch.config;
+
+        MappedContainer {
+            id: container.id.clone(),
+            name: container.name.clone(),
+            key: container_key(&config.docker_host, &container.name),
+            watch: watch.selector.clone(),
+            restart: match config.restart_compose_project {
+                true => compose_project_targets(container),
+                false => watch.restart.clone(),
+            },
+            patterns: watch.patterns.clone(),
+            excludes: watch.excludes.clone(),
+            expect: watch.expect.clone(),
+            events: watch.events.clone(),
+            threshold: config.threshold,
+            window: config
+                .window
+                .filter(|window| *window > 0)
+                .map(Duration::from_secs),
+            stream: config.stream,
+            on_match_command: config.on_match_command.clone(),
+            command_mode: config.command_mode,
+            restart_timeout: config.restart_timeout.or(settings.restart_timeout),
+            restart_delay: config
+                .restart_delay
+                .map(Duration::from_secs)
+                .or(settings.restart_delay),
+            cooldown: config
+                .cooldown
+                .map(Duration::from_secs)
+                .or(settings.cooldown),
+            max_restarts: config.max_restarts.or(settings.max_restarts),
+            signal: config.signal.clone(),
+            invert_restart: config.invert_restart,
+            pre_restart_command: config.pre_restart_command.clone(),
+            post_restart_command: config.post_restart_command.clone(),
+            pre_restart_gate: config.pre_restart_gate,
+            monitoring_since: Instant::now(),
+            tracker: Arc::default(),
+            daemon: settings.health.daemon(&config.docker_host),
+        }
+    }
+}
+
+/// `name` for containers on the default daemon, so existing state files still apply, and
+/// `host/name` for the others.
+fn container_key(host: &Option<String>, name: &str) -> String {
+    match host {
+        Some(host) => format!("{host}/{name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Match and restart history of a watched container, kept by name so it survives recreation.
+#[derive(Debug, Default)]
+struct Tracker {
+    /// The most recent matches, at most `threshold` of them
+    recent_matches: VecDeque<Instant>,
+    /// When the last match that counted toward the threshold happened, for `--debounce`
+    last_counted: Option<Instant>,
+    last_restart: Option<Instant>,
+    /// When each pattern last caused a restart, for `--pattern-cooldown-per-pattern`
+    pattern_restarts: HashMap<String, Instant>,
+    restart_count: u32,
+    tripped: bool,
+    /// Whether this watch has restarted its targets during this run
+    fired: bool,
+    /// The pattern that caused the last restart, until it matched again or `--verify-window`
+    /// passed
+    unverified: Option<String>,
+}
+
+impl Tracker {
+    /// Records a match at `now` and returns how many recent matches count toward the
+    /// threshold.
+    fn record_match(&mut self, now: Instant, threshold: u32, window: Option<Duration>) -> u32 {
+        if let Some(window) = window {
+            while self
+                .recent_matches
+                .front()
+                .is_some_and(|matched| now.duration_since(*matched) >= window)
+            {
+                self.recent_matches.pop_front();
+            }
+        }
+
+        self.recent_matches.push_back(now);
+        if self.recent_matches.len() > threshold as usize {
+            self.recent_matches.pop_front();
+        }
+
+        self.recent_matches.len() as u32
+    }
+
+    /// Picks up the restart history persisted by a previous run.
+    fn restore(&mut self, state: &WatchState) {
+        self.restart_count = state.restart_count;
+        self.last_restart = state.last_restart_ts.and_then(|ts| {
+            let elapsed = Duration::from_secs(now().saturating_sub(ts).max(0) as u64);
+            Instant::now().checked_sub(elapsed)
+        });
+    }
+}
+
+/// Containers in any of `statuses`, which include `restarting` by default so a watched
+/// container that is flapping keeps its monitoring task.
+async fn get_running_containers(
+    docker: &impl DockerOps,
+    statuses: &[String],
+) -> Result<Vec<ContainerSummary>, Box<dyn std::error::Error>> {
+    let statuses: Vec<&str> = statuses.iter().map(String::as_str).collect();
+
+    get_containers_with_status(docker, &statuses).await
+}
+
+async fn get_containers_with_status(
+    docker: &impl DockerOps,
+    statuses: &[&str],
+) -> Result<Vec<ContainerSummary>, Box<dyn std::error::Error>> {
+    let containers = docker.list(statuses).await?;
+
+    debug!("All containers: {containers:?}");
+
+    Ok(containers)
+}
+
+/// Reads the existing logs of every watched container once, restarting targets like the
+/// monitor would, and returns when all of them have been read.
+async fn scan_once(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    watches: &[Watch],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let selectors: Vec<Selector> = watches.iter().map(|w| w.selector.clone()).collect();
+    let containers = get_filtered_containers(docker, settings, &selectors).await?;
+
+    let scans = containers.iter().filter_map(|container| {
+        let watch = watches.get(container.selector)?;
+        let mut mapped = MappedContainer::new(container, watch, settings);
+        let mut tracker = Tracker::default();
+        if let Some(state) = settings.state.as_ref().and_then(|s| s.get(&mapped.key)) {
+            tracker.restore(&state);
+        }
+        mapped.tracker = Arc::new(Mutex::new(tracker));
+
+        Some(async move {
+            info!("[{}] Scanning logs...", mapped.name);
+            if let Err(e) = monitor_logs(docker, settings, &mapped).await {
+                error!("[{}] Error scanning logs: {e}", mapped.name);
+            }
+        })
+    });
+    futures_util::future::join_all(scans).await;
+    flush_state(settings);
+
+    Ok(())
+}
+
+/// Prints one row per container each watch and its restart targets resolve to.
+async fn list_targets(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    watches: &[Watch],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rows = vec![["WATCH", "ROLE", "SELECTOR", "CONTAINER", "ID"].map(String::from)];
+
+    for watch in watches {
+        let watched =
+            get_filtered_containers(docker, settings, std::slice::from_ref(&watch.selector))
+                .await?;
+        let mut targets = watch.restart.clone();
+        if watch.config.restart_compose_project {
+            targets.clear();
+            for target in watched.iter().flat_map(compose_project_targets) {
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+        }
+        let restarted = get_filtered_containers(docker, settings, &targets).await?;
+
+        let roles = [
+            ("watch", vec![watch.selector.clone()], watched),
+            ("restart", targets, restarted),
+        ];
+        for (role, selectors, containers) in roles {
+            for (i, selector) in selectors.iter().enumerate() {
+                let mut matched = containers.iter().filter(|c| c.selector == i).peekable();
+                if matched.peek().is_none() {
+                    rows.push(
+                        [&watch.config.watch, role, &selector.to_string(), "-", "-"]
+                            .map(String::from),
+                    );
+                }
+                for container in matched {
+                    rows.push([
+                        watch.config.watch.clone(),
+                        role.to_string(),
+                        selector.to_string(),
+                        container.name.clone(),
+                        container.id.chars().take(12).collect(),
+                    ]);
+                }
+            }
+        }
+    }
+
+    let widths: Vec<usize> = (0..5)
+        .map(|col| rows.iter().map(|row| row[col].len()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+
+    Ok(())
+}
+
+/// A watch or restart target that matches no running container at startup.
+struct MissingTarget {
+    /// Whether it is the watched container rather than a restart target
+    watched: bool,
+    message: String,
+}
+
+/// Describes every watch or restart target that matches no running container.
+async fn find_missing_targets(
+    docker: &impl DockerOps,
+    statuses: &[String],
+    watches: &[Watch],
+) -> Result<Vec<MissingTarget>, Box<dyn std::error::Error>> {
+    let containers = get_running_containers(docker, statuses).await?;
+    let is_running = |selector: &Selector| {
+        containers
+            .iter()
+            .any(|c| selector.matching_name(c).is_some())
+    };
+
+    let mut missing = Vec::new();
+    for watch in watches {
+        if !is_running(&watch.selector) {
+            missing.push(MissingTarget {
+                watched: true,
+                message: format!(
+                    "Watch '{}' does not match any running container",
+                    watch.selector
+                ),
+            });
+        }
+
+        for target in watch.restart.iter().filter(|target| !is_running(target)) {
+            missing.push(MissingTarget {
+                watched: false,
+                message: format!(
+                    "Restart target '{target}' of '{}' does not match any running container",
+                    watch.selector
+                ),
+            });
+        }
+    }
+
+    Ok(missing)
+}
+
+async fn get_filtered_containers(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    selectors: &[Selector],
+) -> Result<Vec<Container>, Box<dyn std::error::Error>> {
+    let containers = get_running_containers(docker, &settings.container_statuses).await?;
+
+    Ok(select_containers(settings, containers, selectors))
+}
+
+/// Stopped containers matching `selectors`, for `--start-stopped`.
+async fn get_stopped_containers(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    selectors: &[Selector],
+) -> Result<Vec<Container>, Box<dyn std::error::Error>> {
+    let containers = get_containers_with_status(docker, &["exited", "created"]).await?;
+
+    Ok(select_containers(settings, containers, selectors))
+}
+
+fn select_containers(
+    settings: &Settings,
+    containers: Vec<ContainerSummary>,
+    selectors: &[Selector],
+) -> Vec<Container> {
+    let filtered_containers = containers
+        .into_iter()
+        .filter_map(|c| match &c.id {
+            Some(id) => {
+                // A more specific selector wins over `*`, wherever it is listed
+                let (selector, name) = selectors
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, selector)| **selector != Selector::All)
+                    .chain(
+                        selectors
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, selector)| **selector == Selector::All),
+                    )
+                    .find_map(|(i, selector)| Some((i, selector.matching_name(&c)?)))?;
+                let name = name.to_string();
+
+                if settings.is_self(id) {
+                    if settings.self_warned.swap(true, Ordering::Relaxed) {
+                        debug!("[{name}] Skipping the monitor's own container");
+                    } else {
+                        warn!("[{name}] Skipping the monitor's own container. Pass --allow-self to watch or restart it anyway");
+                    }
+                    return None;
+                }
+
+                Some(Container {
+                    id: id.clone(),
+                    name,
+                    selector,
+                    compose_project: c
+                        .labels
+                        .as_ref()
+                        .and_then(|labels| labels.get(COMPOSE_PROJECT_LABEL))
+                        .cloned(),
+                })
+            }
+            _ => None,
+        })
+        .collect();
+
+    debug!("Filtered containers: {filtered_containers:?}");
+
+    filtered_containers
+}
+
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+
+/// Selects every container in the same compose project as `container`.
+fn compose_project_targets(container: &Container) -> Vec<Selector> {
+    match &container.compose_project {
+        Some(project) => vec![Selector::Label {
+            key: COMPOSE_PROJECT_LABEL.to_string(),
+            value: project.clone(),
+        }],
+        None => vec![],
+    }
+}
+
+async fn monitor_logs(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    container: &MappedContainer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let id = container.id.clone();
+    let name = container.name.clone();
+    let patterns = container.patterns.clone();
+    let excludes = container.excludes.clone();
+    let mut since = now() - settings.log_lookback.as_secs() as i64;
+    // Lines from before the monitor last stopped were already matched against, and the
+    // ones before its last restart would only cause another
+    if let Some(state) = settings
+        .state
+        .as_ref()
+        .and_then(|state| state.get(&container.key))
+    {
+        let seen = state.last_seen_ts.max(state.last_restart_ts);
+        since = since.max(seen.unwrap_or(0));
+    }
+    let metrics = settings.metrics.container(&container.key);
+    let mut truncated = false;
+    // Only the first subscription backfills by line count, later ones follow from `since`
+    let mut tail = settings.tail;
+    let mut log_stream;
+    // When the expected pattern must have shown up by. Reconnects don't move it
+    let expect_deadline = || {
+        container
+            .expect
+            .as_ref()
+            .map(|(_, within)| Instant::now() + *within)
+    };
+    let mut deadline = expect_deadline();
+
+    'subscribe: loop {
+        let backfill = tail.take();
+        log_stream = docker.logs(
+            &id,
+            LogsOptions::<String> {
+                stdout: container.stream.stdout(),
+                stderr: container.stream.stderr(),
+                follow: !settings.scan_once,
+                timestamps: true,
+                since: match backfill {
+                    Some(_) => 0,
+                    None => since,
+                },
+                tail: backfill.map_or_else(|| "all".to_string(), |lines| lines.to_string()),
+                ..Default::default()
+            },
+        );
+
+        loop {
+            // Wake up for whichever comes first, the expected pattern's deadline or the
+            // stream going idle for --log-idle-timeout
+            let idle = settings.log_idle_timeout.map(|idle| Instant::now() + idle);
+            let next = match deadline.into_iter().chain(idle).min() {
+                Some(at) => match timeout_at(at, log_stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        let expired = deadline.is_some_and(|at| at <= Instant::now());
+                        let Some((expected, within)) =
+                            container.expect.as_ref().filter(|_| expired)
+                        else {
+                            warn!(
+                                "[{name}] No log output for {}s, resubscribing",
+                                settings.log_idle_timeout.unwrap_or_default().as_secs()
+                            );
+                            break;
+                        };
+
+                        deadline = expect_deadline();
+                        tracing::warn!(pattern = %expected, "[{name}] Expected pattern '{expected}' not seen in the last {}s", within.as_secs());
+                        let mut tracker = container.tracker.lock().await;
+                        let trigger = Trigger {
+                            pattern: expected,
+                            line: "",
+                            arrived: Instant::now(),
+                            cause: Cause::Silence(*within),
+                            context: MatchContext::default(),
+                        };
+                        if let Some(completed) =
+                            remediate(docker, settings, container, &mut tracker, trigger).await
+                        {
+                            if !settings.scan_once {
+                                since = completed;
+                                continue 'subscribe;
+                            }
+                        }
+                        continue;
+                    }
+                },
+                None => log_stream.next().await,
+            };
+            let Some(log_result) = next else {
+                break;
+            };
+
+            match log_result {
+                Ok(log) => {
+                    let arrived = Instant::now();
+                    metrics.record_line();
+                    container.daemon.record_line();
+                    // Invalid UTF-8 (binary output, a multi-byte character split across
+                    // frames) is replaced with U+FFFD rather than dropping the whole line
+                    let (logged_at, mut raw) = timestamp::split_prefix(log.as_ref());
+                    if let Some(state) = &settings.state {
+                        let seen = logged_at.as_deref().and_then(timestamp::parse_rfc3339);
+                        state.record_seen(&container.key, seen.unwrap_or_else(now));
+                    }
+                    if let Some(max) = settings.max_line_bytes.filter(|max| raw.len() > *max) {
+                        if !truncated {
+                            truncated = true;
+                            warn!("[{name}] Truncating log lines longer than {max} bytes before matching");
+                        }
+                        raw = &raw[..max];
+                    }
+                    let mut log_output = String::from_utf8_lossy(raw)
+                        .trim_end_matches(['\r', '\n'])
+                        .to_string();
+                    tracing::debug!("[{name}] New line: '{log_output}'");
+                    if settings.strip_ansi {
+                        log_output = strip_ansi(&log_output).into_owned();
+                    }
+
+                    if let Some((expected, _)) = &container.expect {
+                        if container.stream.includes(&log) && expected.is_match(&log_output) {
+                            tracing::debug!("[{name}] Expected pattern '{expected}' seen");
+                            deadline = expect_deadline();
+                        }
+                    }
+
+                    let mut matched = match container.stream.includes(&log) {
+                        true => patterns
+                            .iter()
+                            .find(|pattern| pattern.is_match(&log_output)),
+                        false => None,
+                    };
+                    if matched.is_some() {
+                        metrics.record_hit();
+                    }
+
+                    if let Some(exclude) = matched.and(
+                        excludes
+                            .iter()
+                            .find(|exclude| exclude.is_match(&log_output)),
+                    ) {
+                        tracing::debug!("[{name}] Match suppressed by exclude pattern '{exclude}'");
+                        matched = None;
+                    }
+
+                    if let Some(pattern) = matched {
+                        if let Some(grace) = settings.startup_grace {
+                            if container.monitoring_since.elapsed() < grace {
+                                tracing::info!(pattern = %pattern, "[{name}] Pattern detected during startup grace period, ignoring it: '{pattern}' -> '{log_output}'");
+                                continue;
+                            }
+                        }
+
+                        let mut tracker = container.tracker.lock().await;
+                        verify_restart(settings, &name, &mut tracker, pattern, &log_output);
+                        // A scan reads all of its lines in one burst, so they are counted
+                        // at the time they were logged for --window and --debounce
+                        let counted_at = match settings.scan_once {
+                            true => scan_instant(logged_at.as_deref()),
+                            false => Instant::now(),
+                        };
+                        if let (Some(debounce), Some(last_counted)) =
+                            (settings.debounce, tracker.last_counted)
+                        {
+                            if counted_at.saturating_duration_since(last_counted) < debounce {
+                                tracing::debug!(
+                                    "[{name}] Match within debounce window, not counting it"
+                                );
+                                continue;
+                            }
+                        }
+                        tracker.last_counted = Some(counted_at);
+                        let count =
+                            tracker.record_match(counted_at, container.threshold, container.window);
+                        metrics.record_match();
+                        let threshold = container.threshold;
+                        tracing::info!(pattern = %pattern, "[{name}] Pattern detected ({count}/{threshold}): '{pattern}' -> '{log_output}'");
+                        if count >= threshold {
+                            let trigger = Trigger {
+                                pattern,
+                                line: &log_output,
+                                arrived,
+                                cause: Cause::Line,
+                                context: MatchContext {
+                                    logged_at: logged_at.clone(),
+                                    stream: Some(stream_name(&log)),
+                                    uptime_secs: None,
+                                },
+                            };
+                            if let Some(completed) =
+                                remediate(docker, settings, container, &mut tracker, trigger).await
+                            {
+                                deadline = expect_deadline();
+                                // A scan reads the rest of the existing logs rather than the
+                                // ones logged after the restart
+                                if !settings.scan_once {
+                                    since = completed;
+                                    continue 'subscribe;
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    settings.metrics.record_docker_error();
+                    error!("[{name}] Failed to read logs: {e}");
+                    break;
+                }
+            }
+        }
+
+        if settings.scan_once {
+            return Ok(());
+        }
+
+        match container_running(docker, &id).await {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("[{name}] Container is no longer running, stopping monitoring");
+                return Ok(());
+            }
+            Err(e) => {
+                settings.metrics.record_docker_error();
+                warn!("[{name}] Failed to inspect container: {e}");
+            }
+        }
+
+        since = now();
+        sleep(settings.resubscribe_delay).await;
+    }
+}
+
+/// Escalates when `pattern` matched again within `--verify-window` of the restart it caused,
+/// meaning the restart didn't fix what it was for. The match still counts as usual.
+fn verify_restart(
+    settings: &Settings,
+    name: &str,
+    tracker: &mut Tracker,
+    pattern: &Matcher,
+    line: &str,
+) {
+    let (Some(window), Some(restarted)) = (settings.verify_window, tracker.last_restart) else {
+        return;
+    };
+    let after = restarted.elapsed();
+    if after >= window {
+        tracker.unverified = None;
+        return;
+    }
+    if tracker.unverified.as_deref() != Some(pattern.to_string().as_str()) {
+        return;
+    }
+
+    tracker.unverified = None;
+    tracing::error!(pattern = %pattern, "[{name}] Restarting didn't help, '{pattern}' matched again {}s after the restart: '{line}'", after.as_secs());
+    settings.notify_slack(
+        name,
+        format!(
+            ":rotating_light: Restarting didn't help: pattern `{pattern}` matched in {name} again {}s after the restart",
+            after.as_secs()
+        ),
+    );
+    settings.notify(
+        name,
+        RestartIneffectiveEvent {
+            event: "restart_ineffective",
+            watch: name.to_string(),
+            pattern: pattern.to_string(),
+            line: line.to_string(),
+            secs_after_restart: after.as_secs(),
+            timestamp: now(),
+        },
+    );
+}
+
+/// Places a line logged at Docker's `logged_at` timestamp (or now, without one) on a clock
+/// shared by every scanned line. Lines can predate `Instant`'s start, so only the
+/// differences between these are meaningful.
+fn scan_instant(logged_at: Option<&str>) -> Instant {
+    static ANCHOR: OnceLock<Instant> = OnceLock::new();
+
+    let logged = logged_at
+        .and_then(timestamp::parse_precise)
+        .and_then(|(secs, nanos)| Some(Duration::new(u64::try_from(secs).ok()?, nanos)));
+    let since_epoch = logged.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    });
+
+    *ANCHOR.get_or_init(Instant::now) + since_epoch
+}
+
+/// What set off a restart, with `pattern` being the matched pattern, expected pattern or
+/// event.
+struct Trigger<'a> {
+    pattern: &'a Matcher,
+    line: &'a str,
+    arrived: Instant,
+    cause: Cause,
+    context: MatchContext,
+}
+
+enum Cause {
+    Line,
+    /// `expect_pattern` wasn't seen for this long
+    Silence(Duration),
+    /// The watched container emitted one of its `on_event` events
+    Event,
+}
+
+/// Applies the cooldown and circuit breaker, runs the match command and restarts the
+/// targets. Returns when the restart completed, for the caller to resubscribe from.
+async fn remediate(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    container: &MappedContainer,
+    tracker: &mut Tracker,
+    trigger: Trigger<'_>,
+) -> Option<i64> {
+    let name = &container.name;
+    let pattern = trigger.pattern;
+
+    if settings.first_match_only && tracker.fired {
+        tracing::info!(pattern = %pattern, "[{name}] Already restarted once, ignoring the match");
+        return None;
+    }
+
+    let last_restart = match settings.cooldown_per_pattern {
+        true => tracker.pattern_restarts.get(&pattern.to_string()).copied(),
+        false => tracker.last_restart,
+    };
+    if let (Some(cooldown), Some(last_restart)) = (container.cooldown, last_restart) {
+        if last_restart.elapsed() < cooldown {
+            tracing::info!(pattern = %pattern, "[{name}] Cooldown active, skipping restart");
+            return None;
+        }
+    }
+
+    if let (Some(window), Some(last_restart)) = (settings.restart_window, tracker.last_restart) {
+        if last_restart.elapsed() >= window {
+            tracker.restart_count = 0;
+            tracker.tripped = false;
+        }
+    }
+
+    if let Some(max_restarts) = container.max_restarts {
+        if tracker.restart_count >= max_restarts {
+            if !tracker.tripped {
+                tracker.tripped = true;
+                tracing::error!("[{name}] Circuit breaker tripped after {max_restarts} restarts, no longer restarting");
+                settings.notify(
+                    name,
+                    BreakerTrippedEvent {
+                        event: "circuit_breaker_tripped",
+                        watch: name.clone(),
+                        restarts: tracker.restart_count,
+                        timestamp: now(),
+                    },
+                );
+            } else {
+                tracing::debug!("[{name}] Circuit breaker open, skipping restart");
+            }
+            return None;
+        }
+    }
+
+    if let Some(command) = &container.on_match_command {
+        if settings.dry_run {
+            tracing::info!(pattern = %pattern, "[{name}] Dry run: would run `{command}`");
+        } else {
+            tracing::info!(pattern = %pattern, "[{name}] Running command: '{command}'");
+            tokio::spawn(command::run_on_match(
+                command.clone(),
+                name.clone(),
+                pattern.to_string(),
+                trigger.line.to_string(),
+            ));
+        }
+    }
+
+    if container.command_mode == CommandMode::Instead {
+        tracker.recent_matches.clear();
+        tracker.fired = true;
+        tracker.restart_count += 1;
+        tracker.last_restart = Some(Instant::now());
+        tracker
+            .pattern_restarts
+            .insert(pattern.to_string(), Instant::now());
+        settings.signal_once();
+        return None;
+    }
+
+    let span = info_span!(
+        "restart",
+        watch = %name,
+        pattern = %pattern,
+        line = trigger.line,
+        silent_for_secs = match trigger.cause {
+            Cause::Silence(silent) => Some(silent.as_secs()),
+            _ => None,
+        },
+        restarted = tracing::field::Empty,
+        error = tracing::field::Empty,
+    );
+    match trigger.cause {
+        Cause::Line => {
+            tracing::info!(parent: &span, pattern = %pattern, "[{name}] Restarting container: '{pattern}' detected in '{}'", trigger.line)
+        }
+        Cause::Silence(silent) => {
+            tracing::info!(parent: &span, pattern = %pattern, "[{name}] Restarting container: '{pattern}' not seen for {}s", silent.as_secs())
+        }
+        Cause::Event => {
+            tracing::info!(parent: &span, pattern = %pattern, "[{name}] Restarting container: '{}' event received", trigger.line)
+        }
+    }
+    let mut context = trigger.context;
+    context.uptime_secs = uptime(docker, &container.id).await;
+    let context_summary = context.to_string();
+    if !context_summary.is_empty() {
+        tracing::info!(parent: &span, "[{name}] Match context: {context_summary}");
+    }
+    let arrived = trigger.arrived;
+    let latency = arrived.elapsed();
+    // Stringify the error so it isn't held across the backoff below
+    let restarted = restart_containers(docker, settings, container)
+        .instrument(span.clone())
+        .await
+        .map_err(|e| e.to_string());
+    match restarted {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(e) => {
+            span.record("error", e.as_str());
+            // Keep monitoring, the next trigger will retry the restart
+            tracing::error!(parent: &span, "[{name}] Failed to restart containers: {e}");
+            let attempts = settings.restart_retries + 1;
+            settings.notify_slack(
+                name,
+                format!(":rotating_light: Remediation is failing: couldn't restart the targets of {name} after {attempts} attempt(s): {e}"),
+            );
+            settings.notify(
+                name,
+                RemediationFailedEvent {
+                    event: "remediation_failed",
+                    watch: name.clone(),
+                    attempts,
+                    error: e,
+                    timestamp: now(),
+                },
+            );
+            sleep(settings.resubscribe_delay).await;
+            return None;
+        }
+    }
+    // Everything from here on is measured from when the restart completed, so neither the
+    // match count nor the next subscription picks up the targets' shutdown logs
+    let completed = now();
+    tracker.recent_matches.clear();
+    tracker.last_counted = None;
+    tracker.fired = true;
+    tracker.unverified = settings.verify_window.map(|_| pattern.to_string());
+    tracker.restart_count += 1;
+    tracker.last_restart = Some(Instant::now());
+    tracker
+        .pattern_restarts
+        .insert(pattern.to_string(), Instant::now());
+
+    // Cooldowns and --max-restarts still apply as if the restart happened, but nothing else
+    // may claim that it did
+    if settings.dry_run {
+        tracing::info!(parent: &span, "[{name}] Dry run: not notifying, recording or persisting the restart");
+        settings.signal_once();
+        return Some(completed);
+    }
+
+    settings
+        .metrics
+        .container(&container.key)
+        .record_restart(latency);
+    tracing::debug!(
+        parent: &span,
+        "[{name}] Restart issued {}ms after the line arrived and took {}ms",
+        latency.as_millis(),
+        (arrived.elapsed() - latency).as_millis()
+    );
+    tracing::info!(parent: &span, "[{name}] Successfully restarted container");
+
+    let restarted: Vec<String> = container
+        .restart
+        .iter()
+        .map(|target| target.to_string())
+        .collect();
+    span.record("restarted", restarted.join(", "));
+    let reason = match trigger.cause {
+        Cause::Line => format!("pattern `{pattern}` matched in {name}"),
+        Cause::Silence(silent) => format!(
+            "pattern `{pattern}` wasn't seen in {name} for {}s",
+            silent.as_secs()
+        ),
+        Cause::Event => format!("{name} reported a `{}` event", trigger.line),
+    };
+    settings.notify_slack(
+        name,
+        format!(
+            ":warning: Restarted {} because {reason}",
+            restarted.join(", ")
+        ),
+    );
+    if let Some(audit) = &settings.audit {
+        let entry = AuditEntry {
+            timestamp: now(),
+            watch: name,
+            pattern: &pattern.to_string(),
+            line: trigger.line,
+            restarted: &restarted,
+            context: &context,
+        };
+        if let Err(e) = audit.record(&entry) {
+            tracing::warn!("[{name}] Failed to write audit file: {e}");
+        }
+    }
+    let event = RestartEvent {
+        watch: name.clone(),
+        pattern: pattern.to_string(),
+        restarted,
+        timestamp: now(),
+        context,
+    };
+    if let Some(hook) = &settings.on_restart {
+        (hook.0)(&event);
+    }
+    settings.history.record(event.clone());
+    settings.notify(name, event);
+
+    if let Some(state) = &settings.state {
+        let watch_state = WatchState {
+            restart_count: tracker.restart_count,
+            last_restart_ts: Some(completed),
+            ..state.get(&container.key).unwrap_or_default()
+        };
+        if let Err(e) = state.record(&container.key, watch_state) {
+            tracing::warn!("[{name}] Failed to write state file: {e}");
+        }
+    }
+
+    settings.signal_once();
+
+    Some(completed)
+}
+
+/// How long the container has been running for, going by its `StartedAt`.
+async fn uptime(docker: &impl DockerOps, id: &str) -> Option<i64> {
+    let started_at = docker.inspect(id).await.ok()?.state?.started_at?;
+    // Containers that never started report year 1
+    let started = timestamp::parse_rfc3339(&started_at).filter(|started| *started > 0)?;
+
+    Some(now() - started)
+}
+
+/// TTY output arrives as `Console`, which is reported as stdout like in `Stream`.
+fn stream_name(log: &LogOutput) -> &'static str {
+    match log {
+        LogOutput::StdErr { .. } => "stderr",
+        LogOutput::StdIn { .. } => "stdin",
+        LogOutput::StdOut { .. } | LogOutput::Console { .. } => "stdout",
+    }
+}
+
+/// Whether the container still exists and is running (or in the middle of restarting).
+async fn container_running(
+    docker: &impl DockerOps,
+    id: &str,
+) -> Result<bool, bollard::errors::Error> {
+    match docker.inspect(id).await {
+        Ok(details) => Ok(details
+            .state
+            .is_some_and(|state| state.running == Some(true) || state.restarting == Some(true))),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns false if nothing was restarted: the global rate limit was reached, or every
+/// target was skipped for having just been restarted by another trigger or by
+/// `pre_restart_gate`. Every target is attempted, and an error is only returned if all of
+/// the attempted ones failed.
+async fn restart_containers(
+    docker: &impl DockerOps,
+    settings: &Settings,
+    container: &MappedContainer,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if let Some(limiter) = &settings.rate_limiter {
+        if !limiter.try_acquire() {
+            tracing::warn!(
+                "[{}] Global restart rate limit reached, skipping restart",
+                container.name
+            );
+            return Ok(false);
+        }
+    }
+
+    let options = container
+        .restart_timeout
+        .map(|t| RestartContainerOptions { t: t as isize });
+
+    // Pair each target with whether it is currently running
+    let mut containers: Vec<(Container, bool)> =
+        get_filtered_containers(docker, settings, &container.restart)
+            .await
+            .inspect_err(|_| settings.metrics.record_docker_error())?
+            .into_iter()
+            .map(|target| (target, true))
+            .collect();
+    // Starting stopped targets would undo --invert-restart
+    if settings.start_stopped && !container.invert_restart {
+        let stopped = get_stopped_containers(docker, settings, &container.restart)
+            .await
+            .inspect_err(|_| settings.metrics.record_docker_error())?;
+        containers.extend(stopped.into_iter().map(|target| (target, false)));
+    }
+
+    // Restart in the order the targets were configured, not Docker's list order
+    containers.sort_by_key(|(target, _)| target.selector);
+
+    let mut succeeded = 0;
+    // Targets deduped by `recent_restarts` or held back by `pre_restart_gate`
+    let mut skipped = 0;
+    let mut failures = Vec::new();
+    for (i, (target, running)) in containers.into_iter().enumerate() {
+        if let Some(delay) = container.restart_delay.filter(|_| i > 0) {
+            sleep(delay).await;
+        }
+
+        if !settings.recent_restarts.allow(&target.id) {
+            tracing::info!(
+                "[{}] {} was just restarted by another trigger, skipping it",
+                container.name,
+                target.name
+            );
+            skipped += 1;
+            continue;
+        }
+
+        let action = match (running, &container.signal, container.invert_restart) {
+            (true, _, true) => "stop".to_string(),
+            (true, None, false) => "restart".to_string(),
+            (true, Some(signal), false) => format!("send {signal} to"),
+            (false, ..) => "start".to_string(),
+        };
+        if settings.dry_run {
+            tracing::info!(
+                "[{}] Dry run: would {action} {} ({})",
+                container.name,
+                target.name,
+                target.id
+            );
+            // Nothing was restarted, so triggers that really restart aren't held back
+            settings.recent_restarts.forget(&target.id);
+            succeeded += 1;
+            continue;
+        }
+
+        if let Some(command) = &container.pre_restart_command {
+            let passed = command::run_hook(command, "Pre-restart", &container.name, &target).await;
+            if !passed && container.pre_restart_gate {
+                tracing::warn!(
+                    "[{}] Not restarting {} because its pre-restart command failed",
+                    container.name,
+                    target.name
+                );
+                // It wasn't restarted, so other triggers may still try
+                settings.recent_restarts.forget(&target.id);
+                skipped += 1;
+                continue;
+            }
+        }
+
+        let span = info_span!("restart_target", container = %target.name, action = %action);
+        let mut retries = 0;
+        let result = loop {
+            let result = async {
+                // Only held for the call itself, so a retry's backoff doesn't hold up others
+                let _permit = match &settings.restart_permits {
+                    Some(permits) => permits.acquire().await.ok(),
+                    None => None,
+                };
+
+                match (running, &container.signal, container.invert_restart) {
+                    (true, _, true) => {
+                        tracing::info!("[{}] Stopping {}", container.name, target.name);
+                        let options = container
+                            .restart_timeout
+                            .map(|t| StopContainerOptions { t: t as i64 });
+                        docker.stop(&target.id, options).await
+                    }
+                    (true, None, false) => docker.restart(&target.id, options).await,
+                    (true, Some(signal), false) => {
+                        tracing::info!("[{}] Sending {signal} to {}", container.name, target.name);
+                        docker.kill(&target.id, signal).await
+                    }
+                    (false, ..) => {
+                        tracing::info!(
+                            "[{}] Starting stopped container {}",
+                            container.name,
+                            target.name
+                        );
+                        docker.start(&target.id).await
+                    }
+                }
+            }
+            .instrument(span.clone())
+            .await;
+
+            match result {
+                Err(e) if retries < settings.restart_retries => {
+                    settings.metrics.record_docker_error();
+                    let delay = RESTART_RETRY_DELAY * 2u32.saturating_pow(retries);
+                    retries += 1;
+                    tracing::warn!(
+                        "[{}] Failed to {action} {}: {e}, retrying in {}s ({retries}/{})",
+                        container.name,
+                        target.name,
+                        delay.as_secs(),
+                        settings.restart_retries
+                    );
+                    sleep(delay).await;
+                }
+                result => break result,
+            }
+        };
+
+        // One failed target (e.g. removed in the meantime) doesn't stop the rest
+        match result {
+            Ok(()) => {
+                succeeded += 1;
+                if let Some(command) = &container.post_restart_command {
+                    command::run_hook(command, "Post-restart", &container.name, &target).await;
+                }
+            }
+            Err(e) => {
+                settings.metrics.record_docker_error();
+                tracing::error!(
+                    "[{}] Failed to {action} {}: {e}",
+                    container.name,
+                    target.name
+                );
+                settings.recent_restarts.forget(&target.id);
+                failures.push(target.name);
+            }
+        }
+    }
+
+    if succeeded == 0 && !failures.is_empty() {
+        return Err(format!("every target failed ({})", failures.join(", ")).into());
+    }
+    if succeeded == 0 && skipped > 0 {
+        tracing::info!(
+            "[{}] Every target was skipped, not counting this as a restart",
+            container.name
+        );
+    }
+    Ok(succeeded > 0)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as i64
+}
+
+/// A moment on tokio's clock that can be moved up to the present without locking, for
+/// measuring how long it has been since something last happened.
+#[derive(Debug)]
+pub(crate) struct AtomicInstant {
+    origin: Instant,
+    offset_ms: AtomicU64,
+}
+
+impl AtomicInstant {
+    pub(crate) fn store_now(&self) {
+        let offset = self.origin.elapsed().as_millis() as u64;
+        self.offset_ms.store(offset, Ordering::Relaxed);
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        let offset = Duration::from_millis(self.offset_ms.load(Ordering::Relaxed));
+        self.origin.elapsed().saturating_sub(offset)
+    }
+}
+
+/// The present moment.
+impl Default for AtomicInstant {
+    fn default() -> Self {
+        AtomicInstant {
+            origin: Instant::now(),
+            offset_ms: AtomicU64::new(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Caps how many restarts may happen across all watches within a rolling window.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit: limit as usize,
+            window,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records a restart and returns true, or returns false if the window is full.
+    pub fn try_acquire(&self) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+
+        while recent
+            .front()
+            .is_some_and(|restart| restart.elapsed() >= self.window)
+        {
+            recent.pop_front();
+        }
+
+        if recent.len() >= self.limit {
+            return false;
+        }
+
+        recent.push_back(Instant::now());
+        true
+    }
+}
+
+/// Lets each key through at most once per interval.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    last_seen: Mutex<HashMap<String, Instant>>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Throttle {
+            interval,
+            last_seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns false if `key` was already let through within the interval.
+    pub fn allow(&self, key: &str) -> bool {
+        let mut last_seen = self.last_seen.lock().unwrap();
+
+        if last_seen
+            .get(key)
+            .is_some_and(|seen| seen.elapsed() < self.interval)
+        {
+            return false;
+        }
+
+        last_seen.insert(key.to_string(), Instant::now());
+        true
+    }
+
+    /// Lets `key` through again, as if it had never been seen.
+    pub fn forget(&self, key: &str) {
+        self.last_seen.lock().unwrap().remove(key);
+    }
+
+    /// Whether `key` was let through within the interval, without letting it through.
+    pub fn contains(&self, key: &str) -> bool {
+        self.last_seen
+            .lock()
+            .unwrap()
+            .get(key)
+            .is_some_and(|seen| seen.elapsed() < self.interval)
+    }
+}
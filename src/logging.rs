@@ -0,0 +1,114 @@
+use env_logger::fmt::style::Style;
+use env_logger::{Builder, Env, WriteStyle};
+use log::kv::Key;
+use log::LevelFilter;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The widest container name logged so far, which text lines pad theirs to so messages
+/// line up. Names longer than `MAX_CONTAINER_WIDTH` aren't padded to.
+static CONTAINER_WIDTH: AtomicUsize = AtomicUsize::new(0);
+const MAX_CONTAINER_WIDTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Installs the global logger. `level` overrides `RUST_LOG`, which otherwise controls
+/// the level in both formats and defaults to `warn`. Text is colored when `color` is set, unless the output
+/// isn't a terminal or `NO_COLOR` is set.
+pub fn init(format: LogFormat, level: Option<LevelFilter>, color: bool) {
+    let mut builder = match level {
+        Some(level) => {
+            let mut builder = Builder::new();
+            builder.filter_level(level);
+            builder
+        }
+        None => Builder::from_env(Env::default().default_filter_or("warn")),
+    };
+
+    if !color {
+        builder.write_style(WriteStyle::Never);
+    }
+
+    match format {
+        LogFormat::Text => builder.format(|buf, record| {
+            let message = record.args().to_string();
+            let (container, message) = split_container(&message);
+            let level = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {level}{:<5}{level:#}] ",
+                buf.timestamp(),
+                record.level()
+            )?;
+
+            // Lines from dependencies keep their module so they can be told apart
+            if !record.target().starts_with(env!("CARGO_CRATE_NAME")) {
+                write!(buf, "{}: ", record.target())?;
+            }
+
+            let name = container.unwrap_or_default();
+            let width = container_width(name);
+            if width > 0 {
+                let bold = Style::new().bold();
+                write!(buf, "{bold}{name:<width$}{bold:#}  ")?;
+            }
+
+            writeln!(buf, "{message}")
+        }),
+        LogFormat::Json => builder.format(|buf, record| {
+            let message = record.args().to_string();
+            let (container, message) = split_container(&message);
+
+            let mut line = Map::new();
+            line.insert("timestamp".into(), buf.timestamp().to_string().into());
+            line.insert("level".into(), record.level().as_str().into());
+            if let Some(container) = container {
+                line.insert("container".into(), container.into());
+            }
+            line.insert("message".into(), message.into());
+            if let Some(pattern) = record.key_values().get(Key::from_str("pattern")) {
+                line.insert("pattern".into(), pattern.to_string().into());
+            }
+
+            writeln!(buf, "{}", Value::Object(line))
+        }),
+    };
+
+    builder.init();
+}
+
+/// Maps `-v` repetitions and `--quiet` to a level, or `None` to defer to `RUST_LOG`
+/// (`warn` when unset), so every step from `--quiet` to `-vvv` logs more than the last.
+pub fn level(verbose: u8, quiet: bool) -> Option<LevelFilter> {
+    match (verbose, quiet) {
+        (_, true) => Some(LevelFilter::Error),
+        (0, false) => None,
+        (1, false) => Some(LevelFilter::Info),
+        (2, false) => Some(LevelFilter::Debug),
+        _ => Some(LevelFilter::Trace),
+    }
+}
+
+/// The width to pad `name` to, widening the column for names up to `MAX_CONTAINER_WIDTH`.
+pub(crate) fn container_width(name: &str) -> usize {
+    match name.len() {
+        len if len <= MAX_CONTAINER_WIDTH => {
+            CONTAINER_WIDTH.fetch_max(len, Ordering::Relaxed).max(len)
+        }
+        _ => CONTAINER_WIDTH.load(Ordering::Relaxed),
+    }
+}
+
+/// Splits the `[container] ` prefix used by the monitoring log lines off the message.
+fn split_container(message: &str) -> (Option<&str>, &str) {
+    message
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .map_or((None, message), |(container, rest)| (Some(container), rest))
+}
@@ -0,0 +1,101 @@
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::LazyLock;
+
+/// CSI sequences (colors, cursor movement), OSC sequences (titles, links) and the
+/// remaining two-byte escapes.
+static ANSI_ESCAPE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]")
+        .expect("ANSI escape regex is valid")
+});
+
+/// Removes ANSI escape sequences so patterns see the plain text of a colorized line.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    ANSI_ESCAPE.replace_all(line, "")
+}
+
+/// The global flags that control how every pattern is compiled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchOptions {
+    pub regex: bool,
+    pub case_insensitive: bool,
+    /// The (trimmed) line must equal the pattern rather than contain it
+    pub whole_line: bool,
+}
+
+/// How a configured pattern is compared against a log line.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Substring(String),
+    /// Holds the original pattern for display and its lowercased form for matching
+    SubstringIgnoreCase {
+        pattern: String,
+        lowered: String,
+    },
+    WholeLine(String),
+    WholeLineIgnoreCase {
+        pattern: String,
+        lowered: String,
+    },
+    /// Keeps the pattern as written for display, `regex` may be anchored
+    Regex {
+        pattern: String,
+        regex: Regex,
+    },
+}
+
+impl Matcher {
+    pub fn new(pattern: &str, options: MatchOptions) -> Result<Self, regex::Error> {
+        if options.regex {
+            let source = match options.whole_line {
+                true => Cow::Owned(format!("^(?:{pattern})$")),
+                false => Cow::Borrowed(pattern),
+            };
+            return Ok(Matcher::Regex {
+                pattern: pattern.to_string(),
+                regex: RegexBuilder::new(&source)
+                    .case_insensitive(options.case_insensitive)
+                    .build()?,
+            });
+        }
+
+        let pattern = pattern.to_string();
+        Ok(match (options.case_insensitive, options.whole_line) {
+            (false, false) => Matcher::Substring(pattern),
+            (true, false) => Matcher::SubstringIgnoreCase {
+                lowered: pattern.to_lowercase(),
+                pattern,
+            },
+            (false, true) => Matcher::WholeLine(pattern),
+            (true, true) => Matcher::WholeLineIgnoreCase {
+                lowered: pattern.to_lowercase(),
+                pattern,
+            },
+        })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Matcher::Substring(pattern) => line.contains(pattern.as_str()),
+            Matcher::SubstringIgnoreCase { lowered, .. } => {
+                line.to_lowercase().contains(lowered.as_str())
+            }
+            Matcher::WholeLine(pattern) => line.trim() == pattern,
+            Matcher::WholeLineIgnoreCase { lowered, .. } => line.trim().to_lowercase() == *lowered,
+            Matcher::Regex { regex, .. } => regex.is_match(line),
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Matcher::Substring(pattern)
+            | Matcher::SubstringIgnoreCase { pattern, .. }
+            | Matcher::WholeLine(pattern)
+            | Matcher::WholeLineIgnoreCase { pattern, .. }
+            | Matcher::Regex { pattern, .. } => write!(f, "{pattern}"),
+        }
+    }
+}
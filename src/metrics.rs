@@ -0,0 +1,184 @@
+use log::{debug, info};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::AtomicInstant;
+
+/// Counters a monitoring task updates without locking.
+#[derive(Debug, Default)]
+pub struct ContainerMetrics {
+    /// Lines since the last rate report
+    lines: AtomicU64,
+    /// Lines over the whole run, for `--match-stats`
+    scanned: AtomicU64,
+    /// Lines a pattern matched, before exclude patterns, debounce and the startup grace
+    hits: AtomicU64,
+    /// Matches that counted toward the threshold, for the shutdown summary
+    matches: AtomicU64,
+    restarts: AtomicU64,
+    /// Milliseconds between the matching line arriving and the last restart being issued
+    last_latency_ms: AtomicU64,
+    /// When the container's watch last restarted its targets, or monitoring first started
+    last_restart: AtomicInstant,
+}
+
+impl ContainerMetrics {
+    pub fn record_line(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_match(&self) {
+        self.matches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_restart(&self, latency: Duration) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+        self.last_latency_ms
+            .store(latency.as_millis() as u64, Ordering::Relaxed);
+        self.last_restart.store_now();
+    }
+
+    /// How long the container has gone without triggering a restart. A short time on a
+    /// container that restarted before means it is flapping.
+    pub fn since_restart(&self) -> Duration {
+        self.last_restart.elapsed()
+    }
+}
+
+/// Per-container metrics, keyed by container name.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    containers: Mutex<HashMap<String, Arc<ContainerMetrics>>>,
+    /// Failed Docker API calls across all containers
+    docker_errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn container(&self, name: &str) -> Arc<ContainerMetrics> {
+        let mut containers = self.containers.lock().unwrap();
+        Arc::clone(containers.entry(name.to_string()).or_default())
+    }
+
+    pub fn record_docker_error(&self) {
+        self.docker_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Logs what happened over the whole run, one line overall and one per container
+    /// that matched or was restarted.
+    pub fn summary(&self, runtime: Duration) {
+        let containers = self.containers.lock().unwrap();
+        let mut names: Vec<&String> = containers.keys().collect();
+        names.sort();
+
+        let total = |counter: fn(&ContainerMetrics) -> &AtomicU64| -> u64 {
+            containers
+                .values()
+                .map(|metrics| counter(metrics).load(Ordering::Relaxed))
+                .sum()
+        };
+        info!(
+            "Ran for {}: {} match(es), {} restart(s), {} Docker error(s)",
+            hms(runtime),
+            total(|metrics| &metrics.matches),
+            total(|metrics| &metrics.restarts),
+            self.docker_errors.load(Ordering::Relaxed)
+        );
+
+        for name in names {
+            let metrics = &containers[name];
+            let matches = metrics.matches.load(Ordering::Relaxed);
+            let restarts = metrics.restarts.load(Ordering::Relaxed);
+            if matches > 0 || restarts > 0 {
+                info!("[{name}] {matches} match(es), {restarts} restart(s)");
+            }
+        }
+    }
+
+    /// Logs how long the container has gone without triggering a restart.
+    pub fn report_uptime(&self, name: &str) {
+        if let Some(metrics) = self.containers.lock().unwrap().get(name) {
+            match metrics.restarts.load(Ordering::Relaxed) {
+                0 => info!("[{name}] No restarts in {}", hms(metrics.since_restart())),
+                restarts => info!(
+                    "[{name}] {} since the last of {restarts} restart(s)",
+                    hms(metrics.since_restart())
+                ),
+            }
+        }
+    }
+
+    /// Logs, for every container, how many lines it logged so far, how many of them a
+    /// pattern matched, how many of those counted toward the threshold and how many
+    /// restarts followed, to spot patterns that match too much.
+    pub fn report_match_stats(&self) {
+        let containers = self.containers.lock().unwrap();
+        let mut names: Vec<&String> = containers.keys().collect();
+        names.sort();
+
+        for name in names {
+            let metrics = &containers[name];
+            let scanned = metrics.scanned.load(Ordering::Relaxed);
+            let hits = metrics.hits.load(Ordering::Relaxed);
+            let share = hits as f64 * 100.0 / scanned.max(1) as f64;
+            info!(
+                "[{name}] {scanned} line(s) scanned, {hits} matched ({share:.1}%), {} counted, {} restart(s)",
+                metrics.matches.load(Ordering::Relaxed),
+                metrics.restarts.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    /// The counters of every container plus the Docker error count, for `GET /metrics`.
+    pub fn to_json(&self) -> String {
+        let containers = self.containers.lock().unwrap();
+        let containers: serde_json::Map<String, serde_json::Value> = containers
+            .iter()
+            .map(|(name, metrics)| {
+                let counters = json!({
+                    "lines": metrics.scanned.load(Ordering::Relaxed),
+                    "pattern_hits": metrics.hits.load(Ordering::Relaxed),
+                    "matches": metrics.matches.load(Ordering::Relaxed),
+                    "restarts": metrics.restarts.load(Ordering::Relaxed),
+                    "secs_since_restart": metrics.since_restart().as_secs(),
+                });
+                (name.clone(), counters)
+            })
+            .collect();
+
+        json!({
+            "containers": containers,
+            "docker_errors": self.docker_errors.load(Ordering::Relaxed),
+        })
+        .to_string()
+    }
+
+    /// Logs the line rate since the previous report and resets the line count.
+    pub fn report(&self, name: &str, elapsed: Duration) {
+        let Some(metrics) = self.containers.lock().unwrap().get(name).cloned() else {
+            return;
+        };
+
+        let lines = metrics.lines.swap(0, Ordering::Relaxed);
+        let rate = lines as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        match metrics.restarts.load(Ordering::Relaxed) {
+            0 => debug!("[{name}] {rate:.1} lines/s"),
+            restarts => debug!(
+                "[{name}] {rate:.1} lines/s, {restarts} restart(s), last {}ms after the match",
+                metrics.last_latency_ms.load(Ordering::Relaxed)
+            ),
+        }
+    }
+}
+
+fn hms(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
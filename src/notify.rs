@@ -0,0 +1,101 @@
+use log::{debug, warn};
+use serde::Serialize;
+use std::fmt;
+
+/// Payload POSTed to `--webhook-url` after a successful restart.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartEvent {
+    pub watch: String,
+    pub pattern: String,
+    pub restarted: Vec<String>,
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub context: MatchContext,
+}
+
+/// What is known about the line behind a restart, each part only when available.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MatchContext {
+    /// When Docker received the line, as it reported it (RFC 3339)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logged_at: Option<String>,
+    /// `stdout` or `stderr`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<&'static str>,
+    /// Seconds the watched container had been running for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<i64>,
+}
+
+impl fmt::Display for MatchContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            self.stream.map(|stream| format!("on {stream}")),
+            self.logged_at.as_ref().map(|at| format!("logged at {at}")),
+            self.uptime_secs
+                .map(|uptime| format!("container up for {uptime}s")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Payload POSTed to `--webhook-url` when a watch stops restarting after `--max-restarts`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerTrippedEvent {
+    pub event: &'static str,
+    pub watch: String,
+    pub restarts: u32,
+    pub timestamp: i64,
+}
+
+/// Payload POSTed to `--webhook-url` when restarting a watch's targets failed, even after
+/// `--restart-retries`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RemediationFailedEvent {
+    pub event: &'static str,
+    pub watch: String,
+    pub attempts: u32,
+    pub error: String,
+    pub timestamp: i64,
+}
+
+/// Payload POSTed to `--webhook-url` when the pattern that caused a restart matched again
+/// within `--verify-window` of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartIneffectiveEvent {
+    pub event: &'static str,
+    pub watch: String,
+    pub pattern: String,
+    pub line: String,
+    pub secs_after_restart: u64,
+    pub timestamp: i64,
+}
+
+/// Message POSTed to `--slack-webhook` after a successful restart.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlackMessage {
+    pub text: String,
+}
+
+pub async fn send_webhook<T: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    watch: &str,
+    payload: &T,
+) {
+    let result = client
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    match result {
+        Ok(_) => debug!("[{watch}] Delivered webhook to {url}"),
+        Err(err) => warn!("[{watch}] Failed to deliver webhook to {url}: {err}"),
+    }
+}
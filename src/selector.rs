@@ -0,0 +1,151 @@
+use bollard::secret::ContainerSummary;
+use regex::Regex;
+use std::fmt;
+
+/// Identifies containers by name, by a `label:key=value` pair, by `image:name[:tag]`,
+/// by `id:` and a full or abbreviated container ID, or every container with `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    Name(String),
+    NamePrefix(String),
+    NameContains(String),
+    NameRegex(NameRegex),
+    Label { key: String, value: String },
+    Image(String),
+    Id(String),
+    All,
+}
+
+/// How a plain name in `watch` is compared to container names, set by `--match-mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MatchMode {
+    #[default]
+    Exact,
+    Prefix,
+    Contains,
+    Regex,
+}
+
+/// A compiled name regex, compared by its source so selectors stay comparable.
+#[derive(Debug, Clone)]
+pub struct NameRegex(Regex);
+
+impl PartialEq for NameRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for NameRegex {}
+
+impl Selector {
+    /// Parses a selector, comparing plain names according to `mode`.
+    pub fn parse_with_mode(raw: &str, mode: MatchMode) -> Result<Self, String> {
+        match Self::parse(raw)? {
+            Selector::Name(name) => match mode {
+                MatchMode::Exact => Ok(Selector::Name(name)),
+                MatchMode::Prefix => Ok(Selector::NamePrefix(name)),
+                MatchMode::Contains => Ok(Selector::NameContains(name)),
+                MatchMode::Regex => Regex::new(&name)
+                    .map(|regex| Selector::NameRegex(NameRegex(regex)))
+                    .map_err(|err| format!("Invalid name regex '{name}': {err}")),
+            },
+            selector => Ok(selector),
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        if raw == "*" {
+            return Ok(Selector::All);
+        }
+
+        if let Some(image) = raw.strip_prefix("image:") {
+            return match image.is_empty() {
+                true => Err("Invalid image selector. Expected format: 'image:name[:tag]'".into()),
+                false => Ok(Selector::Image(image.to_string())),
+            };
+        }
+
+        if let Some(id) = raw.strip_prefix("id:") {
+            return match !id.is_empty() && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                true => Ok(Selector::Id(id.to_ascii_lowercase())),
+                false => Err(format!(
+                    "Invalid ID selector '{raw}'. Expected format: 'id:<full or abbreviated container ID>'"
+                )),
+            };
+        }
+
+        match raw.strip_prefix("label:") {
+            Some(label) => Self::parse_label(label),
+            None => Ok(Selector::Name(raw.to_string())),
+        }
+    }
+
+    /// Parses a bare `key=value` label selector.
+    pub fn parse_label(label: &str) -> Result<Self, String> {
+        match label.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Selector::Label {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!(
+                "Invalid label selector '{label}'. Expected format: 'key=value'"
+            )),
+        }
+    }
+
+    /// `name` is one of the container's names with the leading slash trimmed, see
+    /// [`Selector::matching_name`]. Label, image and ID selectors ignore it.
+    pub fn matches(&self, name: &str, summary: &ContainerSummary) -> bool {
+        match self {
+            Selector::Name(expected) => expected == name,
+            Selector::NamePrefix(prefix) => name.starts_with(prefix.as_str()),
+            Selector::NameContains(part) => name.contains(part.as_str()),
+            Selector::NameRegex(NameRegex(regex)) => regex.is_match(name),
+            Selector::Label { key, value } => summary
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(key))
+                .is_some_and(|actual| actual == value),
+            // An untagged image matches every tag of it, but not a registry port, which is
+            // followed by a path
+            Selector::Image(expected) => summary.image.as_deref().is_some_and(|image| {
+                image == expected
+                    || image
+                        .strip_prefix(expected.as_str())
+                        .is_some_and(|tag| tag.starts_with(':') && !tag.contains('/'))
+            }),
+            Selector::Id(prefix) => summary
+                .id
+                .as_deref()
+                .is_some_and(|id| id.starts_with(prefix.as_str())),
+            Selector::All => true,
+        }
+    }
+
+    /// The first of the container's names (without Docker's leading `/`) that matches. A
+    /// container has several when it has links, and any of them may be the watched one.
+    pub fn matching_name<'a>(&self, summary: &'a ContainerSummary) -> Option<&'a str> {
+        summary
+            .names
+            .iter()
+            .flatten()
+            .map(|name| name.trim_start_matches('/'))
+            .find(|name| self.matches(name, summary))
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Name(name) | Selector::NamePrefix(name) | Selector::NameContains(name) => {
+                write!(f, "{name}")
+            }
+            Selector::NameRegex(NameRegex(regex)) => write!(f, "{}", regex.as_str()),
+            Selector::Label { key, value } => write!(f, "label:{key}={value}"),
+            Selector::Image(image) => write!(f, "image:{image}"),
+            Selector::Id(id) => write!(f, "id:{id}"),
+            Selector::All => write!(f, "*"),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// What is remembered about a watched container across monitor restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    pub restart_count: u32,
+    pub last_restart_ts: Option<i64>,
+    /// When the last line that was matched against was logged, so it isn't scanned again
+    #[serde(default)]
+    pub last_seen_ts: Option<i64>,
+}
+
+/// A JSON file mapping container names to their `WatchState`.
+#[derive(Debug)]
+pub struct StateStore {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, WatchState>>,
+    /// Whether `record_seen` changed anything since the file was last written
+    dirty: AtomicBool,
+}
+
+impl StateStore {
+    /// Loads the store, starting empty if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let entries = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|err| format!("Failed to parse state file '{}': {err}", path.display()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(format!("Failed to read state file '{}': {err}", path.display()).into())
+            }
+        };
+
+        Ok(StateStore {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<WatchState> {
+        self.entries.lock().unwrap().get(name).cloned()
+    }
+
+    /// Updates the entry for `name` and rewrites the file.
+    pub fn record(&self, name: &str, state: WatchState) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(name.to_string(), state);
+
+        self.write(&entries)
+    }
+
+    /// Notes that `name` logged a line at `ts`. Lines arrive too often to rewrite the file
+    /// for each, so it is only written by the next `flush` or `record`.
+    pub fn record_seen(&self, name: &str, ts: i64) {
+        let mut entries = self.entries.lock().unwrap();
+        let state = entries.entry(name.to_string()).or_default();
+        if state.last_seen_ts < Some(ts) {
+            state.last_seen_ts = Some(ts);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Rewrites the file if lines were seen since it was last written.
+    pub fn flush(&self) -> io::Result<()> {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        self.write(&self.entries.lock().unwrap())
+    }
+
+    fn write(&self, entries: &HashMap<String, WatchState>) -> io::Result<()> {
+        self.dirty.store(false, Ordering::Relaxed);
+
+        // Write to a sibling temp file and rename over the original so a crash
+        // mid-write never leaves a truncated state file behind.
+        let contents = serde_json::to_string_pretty(entries)?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
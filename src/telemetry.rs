@@ -0,0 +1,111 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+/// Sends `tracing` events, which the matching and restart paths log through, on to the
+/// `log` output. With an `endpoint`, e.g. `http://collector:4318/v1/traces`, spans are
+/// also exported to that OTLP/HTTP collector, carrying the events logged while they were
+/// open.
+///
+/// The returned provider must be shut down before exiting to flush pending spans.
+pub fn init(
+    endpoint: Option<&str>,
+) -> Result<Option<SdkTracerProvider>, Box<dyn std::error::Error>> {
+    let Some(endpoint) = endpoint else {
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(LogLayer))?;
+        return Ok(None);
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|err| format!("Failed to set up OTLP exporter for '{endpoint}': {err}"))?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")));
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry().with(LogLayer).with(layer),
+    )?;
+
+    Ok(Some(provider))
+}
+
+/// Logs every `tracing` event as a `log` record, with its fields other than the message
+/// as key-values so `--log-format json` still picks them up.
+struct LogLayer;
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = match *metadata.level() {
+            Level::ERROR => log::Level::Error,
+            Level::WARN => log::Level::Warn,
+            Level::INFO => log::Level::Info,
+            Level::DEBUG => log::Level::Debug,
+            Level::TRACE => log::Level::Trace,
+        };
+        let logger = log::logger();
+        let log_metadata = log::Metadata::builder()
+            .level(level)
+            .target(metadata.target())
+            .build();
+        if !logger.enabled(&log_metadata) {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let key_values: Vec<(&str, log::kv::Value)> = fields
+            .values
+            .iter()
+            .map(|(key, value)| (*key, log::kv::Value::from(value.as_str())))
+            .collect();
+
+        logger.log(
+            &log::Record::builder()
+                .metadata(log_metadata)
+                .args(format_args!("{}", fields.message))
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .key_values(&key_values)
+                .build(),
+        );
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    values: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => self.values.push((name, value.to_string())),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            name => self.values.push((name, format!("{value:?}"))),
+        }
+    }
+}
@@ -0,0 +1,1253 @@
+use super::*;
+use bollard::container::{LogOutput, StopContainerOptions};
+use bollard::errors::Error;
+use bollard::secret::{ContainerInspectResponse, EventMessage};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use futures_util::stream::{self, BoxStream};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A fake daemon whose log streams replay canned lines and then end, with the container
+/// reported as stopped so `monitor_logs` returns once they are used up.
+#[derive(Default)]
+struct MockDocker {
+    /// Listed as running unless their `state` says otherwise
+    running: Vec<ContainerSummary>,
+    /// The lines each successive `logs` call replays
+    subscriptions: std::sync::Mutex<VecDeque<Vec<&'static str>>>,
+    /// The `since` of every `logs` call so far
+    since: std::sync::Mutex<Vec<i64>>,
+    /// How many more calls for each container ID fail
+    failing: std::sync::Mutex<HashMap<String, u32>>,
+    restarted: std::sync::Mutex<Vec<String>>,
+    /// Every successful call that acted on a container, as `call id`
+    calls: std::sync::Mutex<Vec<String>>,
+}
+
+impl MockDocker {
+    fn new(running: &[&str], lines: Vec<&'static str>) -> Self {
+        MockDocker {
+            running: running.iter().map(|name| summary(name)).collect(),
+            subscriptions: std::sync::Mutex::new(VecDeque::from([lines])),
+            ..Default::default()
+        }
+    }
+
+    fn failing(self, name: &str) -> Self {
+        self.failing_times(name, u32::MAX)
+    }
+
+    fn failing_times(self, name: &str, times: u32) -> Self {
+        self.failing
+            .lock()
+            .unwrap()
+            .insert(format!("{name}-id"), times);
+        self
+    }
+
+    fn restarted(&self) -> Vec<String> {
+        self.restarted.lock().unwrap().clone()
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: &str, id: &str) -> Result<(), Error> {
+        if let Some(remaining) = self.failing.lock().unwrap().get_mut(id).filter(|n| **n > 0) {
+            *remaining -= 1;
+            return Err(Error::DockerResponseServerError {
+                status_code: 404,
+                message: format!("No such container: {id}"),
+            });
+        }
+
+        self.restarted.lock().unwrap().push(id.to_string());
+        self.calls.lock().unwrap().push(format!("{call} {id}"));
+        Ok(())
+    }
+}
+
+impl DockerOps for MockDocker {
+    async fn list(&self, statuses: &[&str]) -> Result<Vec<ContainerSummary>, Error> {
+        Ok(self
+            .running
+            .iter()
+            .filter(|container| statuses.contains(&container.state.as_deref().unwrap_or("running")))
+            .cloned()
+            .collect())
+    }
+
+    fn logs(
+        &self,
+        _id: &str,
+        options: LogsOptions<String>,
+    ) -> BoxStream<'static, Result<LogOutput, Error>> {
+        self.since.lock().unwrap().push(options.since);
+        let lines = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_default();
+
+        stream::iter(lines.into_iter().map(|line| {
+            Ok(LogOutput::StdOut {
+                message: line.into(),
+            })
+        }))
+        .boxed()
+    }
+
+    async fn inspect(&self, _id: &str) -> Result<ContainerInspectResponse, Error> {
+        Ok(ContainerInspectResponse::default())
+    }
+
+    async fn restart(
+        &self,
+        id: &str,
+        _options: Option<RestartContainerOptions>,
+    ) -> Result<(), Error> {
+        self.record("restart", id)
+    }
+
+    async fn stop(&self, id: &str, _options: Option<StopContainerOptions>) -> Result<(), Error> {
+        self.record("stop", id)
+    }
+
+    async fn kill(&self, id: &str, signal: &str) -> Result<(), Error> {
+        self.record(&format!("kill -s {signal}"), id)
+    }
+
+    async fn start(&self, id: &str) -> Result<(), Error> {
+        self.record("start", id)
+    }
+
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>> {
+        stream::empty().boxed()
+    }
+}
+
+fn summary(name: &str) -> ContainerSummary {
+    ContainerSummary {
+        id: Some(format!("{name}-id")),
+        names: Some(vec![format!("/{name}")]),
+        ..Default::default()
+    }
+}
+
+fn settings(flags: &[&str]) -> Settings {
+    let args = ["docker-restarter", "--watch", "app", "--restart", "db"]
+        .iter()
+        .chain(&["--pattern", "unused", "--allow-self"])
+        .chain(flags);
+
+    Settings::new(&Args::parse_from(args), None, None, None).unwrap()
+}
+
+/// Maps a single YAML watch entry onto the `app` container.
+fn mapped(yaml: &str, settings: &Settings) -> MappedContainer {
+    let config = serde_yaml::from_str(yaml).unwrap();
+    let watch = compile_watches(vec![config], MatchOptions::default(), MatchMode::Exact)
+        .unwrap()
+        .remove(0);
+    let container = Container {
+        id: "app-id".to_string(),
+        name: "app".to_string(),
+        selector: 0,
+        compose_project: None,
+    };
+
+    MappedContainer::new(&container, &watch, settings)
+}
+
+/// A line matching `pattern` that arrived just now.
+fn trigger<'a>(pattern: &'a Matcher, line: &'a str) -> Trigger<'a> {
+    Trigger {
+        pattern,
+        line,
+        arrived: Instant::now(),
+        cause: Cause::Line,
+        context: MatchContext::default(),
+    }
+}
+
+#[tokio::test]
+async fn restarts_targets_when_a_pattern_matches() {
+    let docker = MockDocker::new(&["app", "db"], vec!["starting", "fatal: out of memory"]);
+    let settings = settings(&[]);
+    let container = mapped(
+        "watch: app\nrestart: [db]\npattern: out of memory",
+        &settings,
+    );
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn counts_raw_pattern_hits_for_match_stats() {
+    let lines = vec!["boom", "boom, ignored", "all good", "boom"];
+    let docker = MockDocker::new(&["app", "db"], lines);
+    let settings = settings(&["--match-stats", "1m", "--debounce", "1h"]);
+    let yaml = "watch: app\nrestart: [db]\npattern: boom\nthreshold: 5\nexclude_pattern: ignored";
+    let container = mapped(yaml, &settings);
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    let metrics: serde_json::Value = serde_json::from_str(&settings.metrics.to_json()).unwrap();
+    let app = &metrics["containers"]["app"];
+    assert_eq!(
+        (&app["lines"], &app["pattern_hits"]),
+        (&4.into(), &3.into())
+    );
+    // The exclude pattern and the debounce keep all but the first from counting
+    assert_eq!(app["matches"], 1);
+}
+
+#[tokio::test]
+async fn counts_matches_restarts_and_docker_errors_for_the_summary() {
+    let docker = MockDocker::new(&["app", "db", "cache"], vec!["boom"]).failing("cache");
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db, cache]\npattern: boom", &settings);
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    let metrics: serde_json::Value = serde_json::from_str(&settings.metrics.to_json()).unwrap();
+    let app = &metrics["containers"]["app"];
+    assert_eq!((&app["matches"], &app["restarts"]), (&1.into(), &1.into()));
+    assert_eq!(metrics["docker_errors"], 1);
+}
+
+#[tokio::test]
+async fn resubscribes_from_when_the_restart_completed() {
+    let docker = MockDocker::new(&["app", "db"], vec!["boom", "boom"]);
+    docker.subscriptions.lock().unwrap().push_back(vec!["boom"]);
+    let settings = settings(&[]);
+    let container = mapped(
+        "watch: app\nrestart: [db]\npattern: boom\nthreshold: 2",
+        &settings,
+    );
+    let started = now();
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    // The match before the restart no longer counts toward the next one
+    assert_eq!(docker.restarted(), ["db-id"]);
+    let tracker = container.tracker.lock().await;
+    assert_eq!(tracker.recent_matches.len(), 1);
+    let since = docker.since.lock().unwrap().clone();
+    assert_eq!(since.len(), 2);
+    assert!(since[1] >= started);
+}
+
+#[tokio::test(start_paused = true)]
+async fn serves_the_time_since_the_last_restart() {
+    let docker = MockDocker::new(&["app", "db"], vec!["boom"]);
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+    let since_restart = || {
+        let metrics: serde_json::Value = serde_json::from_str(&settings.metrics.to_json()).unwrap();
+        metrics["containers"]["app"]["secs_since_restart"]
+            .as_u64()
+            .unwrap()
+    };
+
+    // Counted from startup until the first restart
+    settings.metrics.container("app");
+    sleep(Duration::from_millis(2100)).await;
+    assert!(since_restart() >= 2);
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+    assert!(since_restart() <= 1);
+}
+
+#[tokio::test]
+async fn waits_for_the_threshold() {
+    let settings = settings(&[]);
+    let yaml = "watch: app\nrestart: [db]\npattern: boom\nthreshold: 2";
+
+    let docker = MockDocker::new(&["app", "db"], vec!["boom"]);
+    monitor_logs(&docker, &settings, &mapped(yaml, &settings))
+        .await
+        .unwrap();
+    assert!(docker.restarted().is_empty());
+
+    let docker = MockDocker::new(&["app", "db"], vec!["boom", "boom"]);
+    monitor_logs(&docker, &settings, &mapped(yaml, &settings))
+        .await
+        .unwrap();
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn exclude_patterns_suppress_matches() {
+    let docker = MockDocker::new(&["app", "db"], vec!["error: 0"]);
+    let settings = settings(&[]);
+    let container = mapped(
+        "watch: app\nrestart: [db]\npattern: error\nexclude_pattern: 'error: 0'",
+        &settings,
+    );
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    assert!(docker.restarted().is_empty());
+}
+
+#[tokio::test]
+async fn ignores_matches_during_the_startup_grace_period() {
+    let docker = MockDocker::new(&["app", "db"], vec!["boom"]);
+    let settings = settings(&["--startup-grace", "60"]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    assert!(docker.restarted().is_empty());
+}
+
+#[tokio::test]
+async fn cools_down_each_pattern_separately() {
+    let docker = MockDocker::new(&["app"], Vec::new());
+    let yaml = "watch: app\npattern: [alpha, beta]\ncommand_mode: instead";
+    for (flags, restarts) in [
+        (&["--cooldown", "1h"][..], 1),
+        (&["--cooldown", "1h", "--pattern-cooldown-per-pattern"], 2),
+    ] {
+        let settings = settings(flags);
+        let container = mapped(yaml, &settings);
+        let mut tracker = Tracker::default();
+
+        for pattern in [0, 0, 1] {
+            let trigger = trigger(&container.patterns[pattern], "");
+            remediate(&docker, &settings, &container, &mut tracker, trigger).await;
+        }
+
+        assert_eq!(tracker.restart_count, restarts);
+    }
+}
+
+/// Collects the message of every `tracing` event logged in a span, as `span: message`.
+#[derive(Clone, Default)]
+struct SpanEvents(Arc<std::sync::Mutex<Vec<String>>>);
+
+impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanEvents {
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        struct Message(String);
+        impl Visit for Message {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                if field.name() == "message" {
+                    self.0 = format!("{value:?}");
+                }
+            }
+        }
+
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        if let Some(span) = ctx.event_span(event) {
+            let line = format!("{}: {}", span.name(), message.0);
+            self.0.lock().unwrap().push(line);
+        }
+    }
+}
+
+#[tokio::test]
+async fn logs_restarts_on_their_trace() {
+    let events = SpanEvents::default();
+    let _subscriber =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+    let mut tracker = Tracker::default();
+
+    let trigger = trigger(&container.patterns[0], "boom");
+    remediate(&docker, &settings, &container, &mut tracker, trigger).await;
+
+    let events = events.0.lock().unwrap();
+    for message in [
+        "restart: [app] Restarting container: 'boom' detected in 'boom'",
+        "restart: [app] Successfully restarted container",
+    ] {
+        assert!(events.iter().any(|event| event == message), "{events:?}");
+    }
+}
+
+#[tokio::test]
+async fn restarts_only_on_the_first_match() {
+    let docker = MockDocker::new(&["app"], Vec::new());
+    let settings = settings(&["--restart-on-first-match-only"]);
+    let container = mapped(
+        "watch: app\npattern: boom\ncommand_mode: instead",
+        &settings,
+    );
+    let mut tracker = Tracker::default();
+
+    for _ in 0..2 {
+        let trigger = trigger(&container.patterns[0], "boom");
+        remediate(&docker, &settings, &container, &mut tracker, trigger).await;
+    }
+
+    assert_eq!(tracker.restart_count, 1);
+}
+
+#[tokio::test]
+async fn verifies_that_a_restart_helped() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let settings = settings(&["--verify-window", "1m"]);
+    let container = mapped(
+        "watch: app\nrestart: [db]\npattern: [boom, bang]",
+        &settings,
+    );
+    let mut tracker = Tracker::default();
+    let trigger = trigger(&container.patterns[0], "boom");
+
+    remediate(&docker, &settings, &container, &mut tracker, trigger).await;
+    assert_eq!(tracker.unverified.as_deref(), Some("boom"));
+
+    // Only the pattern that caused the restart escalates
+    verify_restart(
+        &settings,
+        "app",
+        &mut tracker,
+        &container.patterns[1],
+        "bang",
+    );
+    assert!(tracker.unverified.is_some());
+    verify_restart(
+        &settings,
+        "app",
+        &mut tracker,
+        &container.patterns[0],
+        "boom",
+    );
+    assert!(tracker.unverified.is_none());
+}
+
+#[test]
+fn triggers_on_the_configured_events() {
+    let settings = settings(&[]);
+    let container = mapped(
+        "watch: app\nrestart: [db]\non_event: [die, health_status]",
+        &settings,
+    );
+
+    let triggered = |action| event_trigger(&container, action).map(ToString::to_string);
+    assert_eq!(triggered("die").as_deref(), Some("die"));
+    assert_eq!(
+        triggered("health_status: unhealthy").as_deref(),
+        Some("health_status")
+    );
+    assert_eq!(triggered("start"), None);
+    assert_eq!(triggered("exec_die"), None);
+}
+
+#[tokio::test]
+async fn keeps_watching_restarting_containers() {
+    let mut docker = MockDocker::new(&["app"], Vec::new());
+    docker.running[0].state = Some("restarting".to_string());
+    let selectors = [Selector::Name("app".to_string())];
+
+    let watched = get_filtered_containers(&docker, &settings(&[]), &selectors).await;
+    assert_eq!(watched.unwrap().len(), 1);
+    let running_only = settings(&["--container-status", "running"]);
+    let watched = get_filtered_containers(&docker, &running_only, &selectors).await;
+    assert!(watched.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn tells_missing_watches_from_missing_targets() {
+    let docker = MockDocker::new(&["db"], Vec::new());
+    let configs = serde_yaml::from_str(
+        "- watch: app\n  pattern: boom\n  restart: [db]\n\
+         - watch: db\n  pattern: boom\n  restart: [cache]",
+    )
+    .unwrap();
+    let watches = compile_watches(configs, MatchOptions::default(), MatchMode::Exact).unwrap();
+    let statuses = ["running".to_string()];
+
+    let missing = find_missing_targets(&docker, &statuses, &watches)
+        .await
+        .unwrap();
+
+    // Only a missing watched container fails --require-all-at-startup
+    let watched: Vec<bool> = missing.iter().map(|target| target.watched).collect();
+    assert_eq!(watched, [true, false]);
+    assert!(missing[0].message.contains("'app'"));
+    assert!(missing[1].message.contains("'cache'"));
+}
+
+#[tokio::test]
+async fn stops_the_targets_with_invert_restart() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let settings = settings(&[]);
+
+    let container = mapped("watch: app\nrestart: [db]\ninvert_restart: true", &settings);
+    assert!(restart_containers(&docker, &settings, &container)
+        .await
+        .unwrap());
+    assert_eq!(docker.calls(), ["stop db-id"]);
+}
+
+#[tokio::test]
+async fn waits_for_a_free_restart_slot() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let settings = settings(&["--max-concurrent-restarts", "1"]);
+    let container = mapped("watch: app\nrestart: [db]", &settings);
+
+    // Another restart holds the only slot
+    let held = settings.restart_permits.as_ref().unwrap().acquire().await;
+    let restart = restart_containers(&docker, &settings, &container);
+    tokio::pin!(restart);
+    let waited = tokio::time::timeout(Duration::from_millis(50), &mut restart).await;
+    assert!(waited.is_err());
+    assert!(docker.restarted().is_empty());
+
+    drop(held);
+    assert!(restart.await.unwrap());
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn restarts_the_remaining_targets_after_a_failure() {
+    let docker = MockDocker::new(&["app", "db", "cache"], Vec::new()).failing("db");
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db, cache]\npattern: boom", &settings);
+
+    let restarted = restart_containers(&docker, &settings, &container).await;
+
+    assert!(restarted.unwrap());
+    assert_eq!(docker.restarted(), ["cache-id"]);
+}
+
+#[tokio::test]
+async fn resolves_selector_targets_at_restart_time() {
+    let mut docker = MockDocker::new(&["app"], Vec::new());
+    let settings = settings(&[]);
+    let container = mapped(
+        "watch: app\nrestart: ['image:redis']\npattern: boom",
+        &settings,
+    );
+    for (name, image) in [("cache", "redis:7"), ("queue", "redis"), ("db", "postgres")] {
+        docker.running.push(ContainerSummary {
+            image: Some(image.to_string()),
+            ..summary(name)
+        });
+    }
+
+    restart_containers(&docker, &settings, &container)
+        .await
+        .unwrap();
+
+    assert_eq!(docker.restarted(), ["cache-id", "queue-id"]);
+}
+
+#[tokio::test]
+async fn gates_restarts_on_the_pre_restart_command() {
+    let settings = settings(&[]);
+    let yaml = "watch: app\nrestart: [db]\npattern: boom\npre_restart_gate: true";
+
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let container = mapped(&format!("{yaml}\npre_restart_command: 'false'"), &settings);
+    let restarted = restart_containers(&docker, &settings, &container).await;
+    assert!(!restarted.unwrap());
+    assert!(docker.restarted().is_empty());
+
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let container = mapped(
+        &format!("{yaml}\npre_restart_command: 'test \"$RESTART_TARGET\" = db'"),
+        &settings,
+    );
+    let restarted = restart_containers(&docker, &settings, &container).await;
+    assert!(restarted.unwrap());
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn fails_when_every_target_fails() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new()).failing("db");
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+
+    assert!(restart_containers(&docker, &settings, &container)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn does_not_count_a_deduped_restart() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+    let (mut first, mut second) = (Tracker::default(), Tracker::default());
+
+    let pattern = &container.patterns[0];
+    remediate(
+        &docker,
+        &settings,
+        &container,
+        &mut first,
+        trigger(pattern, "boom"),
+    )
+    .await;
+    let deduped = remediate(
+        &docker,
+        &settings,
+        &container,
+        &mut second,
+        trigger(pattern, "boom"),
+    )
+    .await;
+
+    assert!(deduped.is_none());
+    assert_eq!((first.restart_count, second.restart_count), (1, 0));
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn does_not_dedupe_a_failed_restart() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new()).failing_times("db", 1);
+    let settings = settings(&[]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+
+    assert!(restart_containers(&docker, &settings, &container)
+        .await
+        .is_err());
+    assert!(restart_containers(&docker, &settings, &container)
+        .await
+        .unwrap());
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn keeps_dry_runs_out_of_the_history_and_state() {
+    let path = std::env::temp_dir().join(format!("restarter-dry-run-{}.json", std::process::id()));
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    let args = ["docker-restarter", "--watch", "app", "--restart", "db"]
+        .iter()
+        .chain(&["--pattern", "boom", "--allow-self", "--dry-run"]);
+    let state = StateStore::load(&path).unwrap();
+    let settings = Settings::new(&Args::parse_from(args), Some(state), None, None).unwrap();
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+    let mut tracker = Tracker::default();
+
+    let pattern = &container.patterns[0];
+    remediate(
+        &docker,
+        &settings,
+        &container,
+        &mut tracker,
+        trigger(pattern, "boom"),
+    )
+    .await;
+
+    assert!(docker.restarted().is_empty());
+    assert_eq!(tracker.restart_count, 1);
+    assert_eq!(settings.history.to_json(), "[]");
+    assert!(settings.state.as_ref().unwrap().get("app").is_none());
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn does_not_run_the_match_command_in_a_dry_run() {
+    let docker = MockDocker::new(&["app"], Vec::new());
+    let marker = |run| std::env::temp_dir().join(format!("restarter-{run}-{}", std::process::id()));
+    let run = |run: &'static str, flags: &'static [&'static str]| {
+        let docker = &docker;
+        async move {
+            let settings = settings(flags);
+            let yaml = format!(
+                "watch: app\npattern: boom\ncommand_mode: instead\non_match_command: touch {}",
+                marker(run).display()
+            );
+            let container = mapped(&yaml, &settings);
+            let mut tracker = Tracker::default();
+            let trigger = trigger(&container.patterns[0], "boom");
+            remediate(docker, &settings, &container, &mut tracker, trigger).await;
+        }
+    };
+
+    run("dry", &["--dry-run"]).await;
+    run("real", &[]).await;
+    // The real run's command finishing shows the dry run's had time to run too
+    for _ in 0..100 {
+        if marker("real").exists() {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    std::fs::remove_file(marker("real")).unwrap();
+    assert!(!marker("dry").exists());
+}
+
+#[tokio::test]
+async fn signals_once_after_a_dry_run_or_instead_command() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new());
+    for (flags, yaml) in [
+        (
+            &["--dry-run"][..],
+            "watch: app\nrestart: [db]\npattern: boom",
+        ),
+        (
+            &[][..],
+            "watch: app\npattern: boom\ncommand_mode: instead\non_match_command: 'true'",
+        ),
+    ] {
+        let (tx, mut rx) = mpsc::channel(1);
+        let settings = Settings {
+            once: Some(tx),
+            ..settings(flags)
+        };
+        let container = mapped(yaml, &settings);
+        let mut tracker = Tracker::default();
+        let trigger = trigger(&container.patterns[0], "boom");
+
+        remediate(&docker, &settings, &container, &mut tracker, trigger).await;
+
+        assert!(rx.try_recv().is_ok(), "{flags:?}");
+    }
+    assert!(docker.restarted().is_empty());
+}
+
+#[tokio::test]
+async fn retries_a_failed_restart() {
+    let docker = MockDocker::new(&["app", "db"], Vec::new()).failing_times("db", 1);
+    let settings = settings(&["--restart-retries", "1"]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+
+    let restarted = restart_containers(&docker, &settings, &container).await;
+
+    assert!(restarted.unwrap());
+    assert_eq!(docker.restarted(), ["db-id"]);
+}
+
+#[tokio::test]
+async fn monitor_validates_its_configs() {
+    let docker = || Docker::connect_with_http_defaults().unwrap();
+    let config = ContainerRestartConfig::new("app", vec!["db".into()], "boom");
+    assert!(Monitor::new(vec![config], docker()).is_ok());
+
+    let config = ContainerRestartConfig::new("app", Vec::new(), "boom");
+    assert!(Monitor::new(vec![config], docker()).is_err());
+
+    let config = ContainerRestartConfig {
+        docker_host: Some("tcp://10.0.0.2:2375".into()),
+        ..ContainerRestartConfig::new("app", vec!["db".into()], "boom")
+    };
+    assert!(Monitor::new(vec![config], docker()).is_err());
+
+    let config = ContainerRestartConfig {
+        threshold: 0,
+        ..ContainerRestartConfig::new("app", vec!["db".into()], "boom")
+    };
+    assert!(Monitor::new(vec![config], docker()).is_err());
+
+    let config = ContainerRestartConfig {
+        max_restarts: Some(0),
+        ..ContainerRestartConfig::new("app", vec!["db".into()], "boom")
+    };
+    assert!(Monitor::new(vec![config], docker()).is_err());
+}
+
+#[test]
+fn groups_watches_by_docker_host() {
+    let configs = serde_yaml::from_str(
+        "- watch: api\n  pattern: boom\n  restart: [db]\n\
+         - watch: web\n  pattern: boom\n  restart: [db]\n  docker_host: tcp://10.0.0.2:2375",
+    )
+    .unwrap();
+    let watches = compile_watches(configs, MatchOptions::default(), MatchMode::Exact).unwrap();
+
+    let remote = watches_on(&watches, &Some("tcp://10.0.0.2:2375".to_string()));
+    let local = watches_on(&watches, &None);
+
+    assert_eq!(remote.len(), 1);
+    assert_eq!(remote[0].config.watch, "web");
+    assert_eq!(local[0].config.watch, "api");
+}
+
+#[test]
+fn keys_containers_by_docker_host() {
+    let on = |host: &str| ContainerRestartConfig {
+        docker_host: Some(host.into()),
+        ..ContainerRestartConfig::new("app", vec!["db".into()], "boom")
+    };
+    let twice = [on("tcp://10.0.0.2:2375"), on("tcp://10.0.0.2:2375")];
+    assert!(config::validate_configs(&twice).is_err());
+    let apart = [on("tcp://10.0.0.2:2375"), on("tcp://10.0.0.3:2375")];
+    assert!(config::validate_configs(&apart).is_ok());
+
+    let settings = settings(&[]);
+    let local = mapped("watch: app\npattern: boom\nrestart: [db]", &settings);
+    let remote = mapped(
+        "watch: app\npattern: boom\nrestart: [db]\ndocker_host: tcp://10.0.0.2:2375",
+        &settings,
+    );
+    assert_eq!(local.key, "app");
+    assert_eq!(remote.key, "tcp://10.0.0.2:2375/app");
+}
+
+#[test]
+fn requires_every_daemon_to_be_healthy() {
+    let health = health::Health::new(Duration::from_secs(60));
+    assert!(!health.is_healthy());
+
+    let local = health.daemon(&None);
+    let remote = health.daemon(&Some("tcp://10.0.0.2:2375".to_string()));
+    health.record_poll(&local);
+    assert!(!health.is_healthy());
+    health.record_poll(&remote);
+    assert!(health.is_healthy());
+
+    // Each daemon's containers feed its own stall watchdog
+    assert!(Arc::ptr_eq(&health.daemon(&None), &local));
+    assert!(!Arc::ptr_eq(&local, &remote));
+}
+
+#[tokio::test]
+async fn pings_the_heartbeat_once_every_daemon_was_polled() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/ping", listener.local_addr().unwrap());
+    let health = Arc::new(health::Health::new(Duration::from_secs(60)));
+    let local = health.daemon(&None);
+    let remote = health.daemon(&Some("tcp://10.0.0.2:2375".to_string()));
+    let heartbeat_health = Arc::clone(&health);
+    tokio::spawn(async move {
+        health::heartbeat(&heartbeat_health, &reqwest::Client::new(), &url).await;
+    });
+
+    health.record_poll(&local);
+    let pinged = tokio::time::timeout(Duration::from_millis(200), listener.accept()).await;
+    assert!(pinged.is_err());
+
+    health.record_poll(&remote);
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request = [0; 64];
+    let read = stream.read(&mut request).await.unwrap();
+    assert!(request[..read].starts_with(b"GET /ping "));
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+        .await
+        .unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn detects_a_stalled_daemon() {
+    let settings = settings(&["--stall-timeout", "1s"]);
+    let health = health::Health::new(Duration::from_secs(60));
+    let local = health.daemon(&None);
+    let remote = health.daemon(&Some("tcp://10.0.0.2:2375".to_string()));
+    assert!(!stalled(&settings, &local, "Docker", 1));
+
+    sleep(Duration::from_millis(2100)).await;
+    remote.record_line();
+    assert!(!stalled(&settings, &local, "Docker", 0));
+    assert!(!stalled(
+        &settings,
+        &remote,
+        "Docker at tcp://10.0.0.2:2375",
+        1
+    ));
+    assert!(stalled(&settings, &local, "Docker", 1));
+    // The reopened streams get a full timeout of their own
+    assert!(!stalled(&settings, &local, "Docker", 1));
+}
+
+#[test]
+fn maps_verbosity_flags_to_levels() {
+    use log::LevelFilter;
+    use logging::level;
+
+    assert_eq!(level(0, true), Some(LevelFilter::Error));
+    // RUST_LOG decides, `warn` when it isn't set
+    assert_eq!(level(0, false), None);
+    assert_eq!(level(1, false), Some(LevelFilter::Info));
+    assert_eq!(level(2, false), Some(LevelFilter::Debug));
+    assert_eq!(level(3, false), Some(LevelFilter::Trace));
+    assert_eq!(level(5, false), Some(LevelFilter::Trace));
+}
+
+#[test]
+fn aligns_container_names() {
+    use logging::container_width;
+
+    // The column widens to the longest name so far, but not for overly long ones
+    assert_eq!(container_width("api"), 3);
+    assert_eq!(container_width("worker"), 6);
+    assert_eq!(container_width("api"), 6);
+    assert_eq!(container_width(&"x".repeat(40)), 6);
+    assert_eq!(container_width(""), 6);
+}
+
+#[test]
+fn parses_durations() {
+    use duration::{parse_duration, parse_millis};
+
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_millis("250"), Ok(Duration::from_millis(250)));
+    assert!(parse_duration("5 minutes").is_err());
+    assert!(parse_duration("m").is_err());
+}
+
+#[test]
+fn splits_docker_timestamps() {
+    use timestamp::{parse_precise, parse_rfc3339, split_prefix};
+
+    assert_eq!(parse_rfc3339("1970-01-02T00:00:00Z"), Some(86400));
+    assert_eq!(
+        parse_rfc3339("2024-03-01T12:30:00.123456789+02:00"),
+        Some(1709289000)
+    );
+    assert_eq!(parse_rfc3339("0001-01-01T00:00:00Z"), Some(-62135596800));
+    assert_eq!(
+        parse_precise("1970-01-01T00:00:01.5Z"),
+        Some((1, 500_000_000))
+    );
+
+    let (logged_at, line) = split_prefix(b"2024-03-01T10:30:00.5Z fatal: boom");
+    assert_eq!(logged_at.as_deref(), Some("2024-03-01T10:30:00.5Z"));
+    assert_eq!(line, b"fatal: boom");
+    assert_eq!(split_prefix(b"fatal: boom"), (None, &b"fatal: boom"[..]));
+}
+
+#[test]
+fn matches_whole_lines() {
+    let whole_line = |regex, case_insensitive, pattern| {
+        let options = MatchOptions {
+            regex,
+            case_insensitive,
+            whole_line: true,
+        };
+        Matcher::new(pattern, options).unwrap()
+    };
+
+    let plain = whole_line(false, false, "fatal");
+    assert!(plain.is_match("  fatal "));
+    assert!(!plain.is_match("fatal: out of memory"));
+    assert!(whole_line(false, true, "fatal").is_match("FATAL"));
+    let regex = whole_line(true, false, "fatal|panic");
+    assert!(regex.is_match("panic"));
+    assert!(!regex.is_match("kernel panic"));
+}
+
+#[test]
+fn matches_containers_by_id() {
+    let container = ContainerSummary {
+        id: Some("4f2a9c1b7e3d0a".to_string()),
+        ..summary("app")
+    };
+
+    assert!(Selector::parse("id:4F2A9C")
+        .unwrap()
+        .matches("app", &container));
+    assert!(!Selector::parse("id:2a9c")
+        .unwrap()
+        .matches("app", &container));
+    assert!(Selector::parse("id:").is_err());
+    assert!(Selector::parse("id:not-an-id").is_err());
+}
+
+#[test]
+fn matches_images_by_tag_but_not_registry_port() {
+    let image = |image: &str| ContainerSummary {
+        image: Some(image.to_string()),
+        ..summary("app")
+    };
+    let selector = Selector::parse("image:localhost").unwrap();
+
+    assert!(selector.matches("app", &image("localhost")));
+    assert!(selector.matches("app", &image("localhost:latest")));
+    assert!(!selector.matches("app", &image("localhost:5000/app")));
+    assert!(Selector::parse("image:localhost:5000/app")
+        .unwrap()
+        .matches("app", &image("localhost:5000/app:1.2")));
+}
+
+#[test]
+fn matches_any_of_a_containers_names() {
+    let settings = settings(&[]);
+    let container = ContainerSummary {
+        names: Some(vec!["/app".to_string(), "/web/backend".to_string()]),
+        ..summary("app")
+    };
+    let selectors = [Selector::parse("web/backend").unwrap()];
+
+    let selected = select_containers(&settings, vec![container], &selectors);
+
+    assert_eq!(selected.len(), 1);
+    assert_eq!(selected[0].name, "web/backend");
+}
+
+#[test]
+fn keeps_the_most_recent_restarts() {
+    let record = |history: &History, watch: &str| {
+        history.record(RestartEvent {
+            watch: watch.to_string(),
+            pattern: "boom".to_string(),
+            restarted: vec!["db".to_string()],
+            timestamp: 0,
+            context: MatchContext::default(),
+        })
+    };
+    let history = History::new(2);
+    for watch in ["first", "second", "third"] {
+        record(&history, watch);
+    }
+
+    let events: Vec<serde_json::Value> = serde_json::from_str(&history.to_json()).unwrap();
+    let watches: Vec<_> = events.iter().map(|event| &event["watch"]).collect();
+    assert_eq!(watches, ["second", "third"]);
+
+    // Nothing is allocated up front for a huge --history-size
+    let unbounded = History::new(usize::MAX);
+    record(&unbounded, "first");
+    assert_eq!(unbounded.to_json().matches("first").count(), 1);
+}
+
+#[test]
+fn splits_comma_separated_watches() {
+    let args = [
+        "docker-restarter",
+        "--watch",
+        "api, worker",
+        "--watch",
+        "db",
+    ]
+    .iter()
+    .chain(&["--restart", "cache", "--restart", "api"])
+    .chain(&["--pattern", "boom", "--pattern", "OOM"]);
+    let watches = ["api, worker".to_string(), "db".to_string()];
+
+    let configs = cli::build_cli_configs(&Args::parse_from(args), &watches).unwrap();
+
+    let watches: Vec<_> = configs.iter().map(|c| c.watch.as_str()).collect();
+    assert_eq!(watches, ["api", "worker", "db"]);
+    assert_eq!(configs[1].restart, ["cache"]);
+}
+
+#[test]
+fn checks_the_config_file() {
+    let path = std::env::temp_dir().join(format!("restarter-check-{}.yaml", std::process::id()));
+    let path_arg = path.to_str().unwrap();
+    let check = |yaml: &str| {
+        std::fs::write(&path, yaml).unwrap();
+        let args = ["docker-restarter", "--config", path_arg, "--config-check"];
+        let matches = Args::command().get_matches_from(args);
+        cli::build_configs(&Args::from_arg_matches(&matches).unwrap(), &matches)
+    };
+
+    let valid = check("- watch: app\n  restart: [db]\n  pattern: boom\n");
+    let gated = check("- watch: app\n  restart: [db]\n  pattern: boom\n  pre_restart_gate: true\n");
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(valid.unwrap().len(), 1);
+    assert!(gated.unwrap_err().to_string().contains("pre_restart_gate"));
+    let listed = [
+        "docker-restarter",
+        "--config",
+        path_arg,
+        "--config-check",
+        "--list",
+    ];
+    assert!(Args::try_parse_from(listed).is_err());
+}
+
+#[test]
+fn keeps_commas_in_regex_patterns() {
+    let args = [
+        "docker-restarter",
+        "--watch",
+        "app",
+        "--restart",
+        "db",
+        "--regex",
+    ]
+    .iter()
+    .chain(&["--pattern", r"retry \d{1,3} times"])
+    .chain(&["--exclude-pattern", "a,b"]);
+    let configs = cli::build_cli_configs(&Args::parse_from(args), &["app".to_string()]).unwrap();
+
+    assert_eq!(configs[0].pattern, [r"retry \d{1,3} times"]);
+    assert_eq!(configs[0].exclude_pattern, ["a,b"]);
+    let options = MatchOptions {
+        regex: true,
+        ..MatchOptions::default()
+    };
+    let watches = compile_watches(configs, options, MatchMode::Exact).unwrap();
+    assert!(watches[0].patterns[0].is_match("retry 12 times"));
+}
+
+#[test]
+fn expands_env_vars_in_every_pattern() {
+    let mut config: ContainerRestartConfig = serde_yaml::from_str(
+        "watch: app\nrestart: [db]\npattern: '${RESTARTER_UNSET_A:-boom}'\nexclude_pattern: '${RESTARTER_UNSET_B:-ok}$'\nexpect_pattern: '$${RESTARTER_UNSET_C:-tick}'",
+    )
+    .unwrap();
+
+    config::expand_env_vars(&mut config).unwrap();
+
+    assert_eq!(config.pattern, ["boom"]);
+    assert_eq!(config.exclude_pattern, ["ok$"]);
+    assert_eq!(
+        config.expect_pattern.as_deref(),
+        Some("${RESTARTER_UNSET_C:-tick}")
+    );
+}
+
+#[test]
+fn reads_patterns_from_a_file() {
+    let path = std::env::temp_dir().join(format!("restarter-patterns-{}", std::process::id()));
+    std::fs::write(&path, "# known crashes\nout of memory\n\n  segfault  \n").unwrap();
+    let path = path.to_str().unwrap();
+    let args = [
+        "docker-restarter",
+        "--watch",
+        "app",
+        "--restart",
+        "db",
+        "--pattern-file",
+        path,
+    ];
+
+    let configs = cli::build_cli_configs(&Args::parse_from(args), &["app".to_string()]);
+    std::fs::remove_file(path).unwrap();
+    let watches =
+        compile_watches(configs.unwrap(), MatchOptions::default(), MatchMode::Exact).unwrap();
+
+    let patterns: Vec<String> = watches[0].patterns.iter().map(Matcher::to_string).collect();
+    assert_eq!(patterns, ["out of memory", "segfault"]);
+}
+
+#[test]
+fn persists_the_last_line_seen() {
+    let path = std::env::temp_dir().join(format!("restarter-state-{}.json", std::process::id()));
+    let state = StateStore::load(&path).unwrap();
+    state.record_seen("api", 200);
+    state.record_seen("api", 100);
+    state.flush().unwrap();
+
+    let seen = StateStore::load(&path).unwrap().get("api").unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(seen.last_seen_ts, Some(200));
+    assert_eq!(seen.restart_count, 0);
+}
+
+#[tokio::test]
+async fn retries_the_startup_connection() {
+    let args = ["--connect-retries", "2", "--connect-retry-delay", "10ms"];
+    let args = Args::parse_from(
+        ["docker-restarter", "--config", "unused.yaml"]
+            .iter()
+            .chain(&args),
+    );
+    let attempts = &std::sync::atomic::AtomicU32::new(0);
+    let connect = |failures| {
+        attempts.store(0, Ordering::Relaxed);
+        cli::connect_with_retries(&args, move || {
+            let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+            async move {
+                match attempt < failures {
+                    true => Err("Connection refused".into()),
+                    false => Ok(attempt),
+                }
+            }
+        })
+    };
+
+    assert_eq!(connect(2).await.unwrap(), 2);
+    assert!(connect(3).await.is_err());
+    assert_eq!(attempts.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test]
+async fn connects_to_named_pipes_only_on_windows() {
+    let connect = |host| cli::connect_docker_host(host, None);
+
+    assert_eq!(
+        connect("npipe:////./pipe/docker_engine").is_ok(),
+        cfg!(windows)
+    );
+    assert!(connect("tcp://10.0.0.2:2375").is_ok());
+    assert!(connect("ssh://10.0.0.2").is_err());
+}
+
+#[test]
+fn resolves_docker_contexts() {
+    let config = std::env::temp_dir().join(format!("restarter-contexts-{}", std::process::id()));
+    let meta = config.join("contexts/meta/0123abcd");
+    std::fs::create_dir_all(&meta).unwrap();
+    std::fs::write(
+        meta.join("meta.json"),
+        r#"{"Name":"remote","Endpoints":{"docker":{"Host":"tcp://10.0.0.2:2376"}}}"#,
+    )
+    .unwrap();
+
+    let remote = context::resolve_in(&config, "remote").unwrap();
+    let missing = context::resolve_in(&config, "missing").unwrap();
+    std::fs::remove_dir_all(&config).unwrap();
+
+    let remote = remote.unwrap();
+    assert_eq!(remote.host, "tcp://10.0.0.2:2376");
+    // No certificates were stored for it
+    assert!(remote.tls.is_none());
+    assert!(missing.is_none());
+}
+
+#[tokio::test]
+async fn scans_the_logs_once() {
+    let docker = MockDocker::new(&["app", "db"], vec!["boom"]);
+    docker.subscriptions.lock().unwrap().push_back(vec!["boom"]);
+    let settings = settings(&["--scan-once"]);
+    let watches = compile_watches(
+        vec![serde_yaml::from_str("watch: app\nrestart: [db]\npattern: boom").unwrap()],
+        MatchOptions::default(),
+        MatchMode::Exact,
+    )
+    .unwrap();
+
+    scan_once(&docker, &settings, &watches).await.unwrap();
+
+    assert_eq!(docker.restarted(), ["db-id"]);
+    assert_eq!(docker.subscriptions.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn scans_past_a_restart() {
+    let docker = MockDocker::new(&["app", "db"], vec!["boom", "boom again"]);
+    let settings = settings(&["--scan-once"]);
+    let container = mapped("watch: app\nrestart: [db]\npattern: boom", &settings);
+
+    monitor_logs(&docker, &settings, &container).await.unwrap();
+
+    // The second match was read and counted, its restart deduped by the first
+    assert_eq!(docker.restarted(), ["db-id"]);
+    assert_eq!(container.tracker.lock().await.recent_matches.len(), 1);
+}
+
+#[tokio::test]
+async fn scans_windows_by_the_logged_time() {
+    let yaml = "watch: app\nrestart: [db]\npattern: boom\nthreshold: 2\nwindow: 60";
+    for (lines, restarted) in [
+        (
+            vec!["2024-01-01T00:00:00Z boom", "2024-01-01T00:05:00Z boom"],
+            0,
+        ),
+        (
+            vec!["2024-01-01T00:00:00Z boom", "2024-01-01T00:00:30Z boom"],
+            1,
+        ),
+    ] {
+        let docker = MockDocker::new(&["app", "db"], lines);
+        let settings = settings(&["--scan-once"]);
+        let container = mapped(yaml, &settings);
+
+        monitor_logs(&docker, &settings, &container).await.unwrap();
+
+        assert_eq!(docker.restarted().len(), restarted);
+    }
+}
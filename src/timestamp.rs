@@ -0,0 +1,85 @@
+/// Seconds since the Unix epoch of an RFC 3339 timestamp like Docker's
+/// `2024-01-02T03:04:05.123456789Z`. Fractions of a second are dropped.
+pub fn parse_rfc3339(raw: &str) -> Option<i64> {
+    parse_precise(raw).map(|(secs, _)| secs)
+}
+
+/// Like [`parse_rfc3339`], also returning the fraction of a second in nanoseconds.
+pub fn parse_precise(raw: &str) -> Option<(i64, u32)> {
+    let bytes = raw.as_bytes();
+    if bytes.len() < 20 || !matches!(bytes[10], b'T' | b't') {
+        return None;
+    }
+
+    let field = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = raw.get(range)?;
+        match digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    };
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if [bytes[4], bytes[7], bytes[13], bytes[16]] != [b'-', b'-', b':', b':']
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+    {
+        return None;
+    }
+
+    let mut rest = &raw[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        // Digits past nanoseconds are dropped, missing ones are zeroes
+        nanos = fraction[..digits.min(9)]
+            .bytes()
+            .chain(std::iter::repeat(b'0'))
+            .take(9)
+            .fold(0, |nanos, digit| nanos * 10 + u32::from(digit - b'0'));
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = rest[1..].split_once(':')?;
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some((secs, nanos))
+}
+
+/// Splits the timestamp Docker puts in front of every line with `timestamps` enabled off
+/// `line`, leaving lines without one as they are.
+pub fn split_prefix(line: &[u8]) -> (Option<String>, &[u8]) {
+    let Some(space) = line.iter().position(|b| *b == b' ') else {
+        return (None, line);
+    };
+
+    match std::str::from_utf8(&line[..space])
+        .ok()
+        .filter(|prefix| parse_rfc3339(prefix).is_some())
+    {
+        Some(prefix) => (Some(prefix.to_string()), &line[space + 1..]),
+        None => (None, line),
+    }
+}
+
+/// Days between 1970-01-01 and the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}